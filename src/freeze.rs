@@ -0,0 +1,108 @@
+use crate::utils::days_since_epoch;
+use anyhow::Context;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A period during which live runs only print the plan, like a release freeze
+#[derive(Debug, PartialEq)]
+pub(crate) struct FreezeWindow {
+    /// Seconds since the Unix epoch, inclusive
+    start: u64,
+    /// Seconds since the Unix epoch, exclusive
+    end: u64,
+    /// The end as written in the schedule, to be shown to the operator
+    pub(crate) until: String,
+}
+
+/// Parses a comma-separated schedule of `start..end` windows. Both ends are UTC times written as
+/// `YYYY-MM-DD` (midnight) or `YYYY-MM-DDTHH:MM`.
+pub(crate) fn parse_schedule(schedule: &str) -> anyhow::Result<Vec<FreezeWindow>> {
+    schedule
+        .split(',')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .map(|window| {
+            let (start, end) = window
+                .split_once("..")
+                .with_context(|| format!("freeze window '{window}' isn't written as start..end"))?;
+            let window = FreezeWindow {
+                start: parse_time(start.trim())?,
+                end: parse_time(end.trim())?,
+                until: end.trim().to_string(),
+            };
+            anyhow::ensure!(
+                window.start < window.end,
+                "freeze window '{start}..{end}' ends before it starts"
+            );
+            Ok(window)
+        })
+        .collect()
+}
+
+/// The window of the schedule that is currently active, if any
+pub(crate) fn active_window(windows: &[FreezeWindow]) -> anyhow::Result<Option<&FreezeWindow>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(window_at(windows, now))
+}
+
+fn window_at(windows: &[FreezeWindow], now: u64) -> Option<&FreezeWindow> {
+    windows.iter().find(|w| w.start <= now && now < w.end)
+}
+
+fn parse_time(time: &str) -> anyhow::Result<u64> {
+    let invalid = || format!("invalid freeze time '{time}', expected YYYY-MM-DD[THH:MM] in UTC");
+    let (date, clock) = time.split_once('T').unwrap_or((time, "00:00"));
+    let date = date
+        .splitn(3, '-')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(invalid)?;
+    let clock = clock
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u64>().ok()?, m.parse::<u64>().ok()?)));
+    let (&[year, month, day], Some((hour, minute))) = (date.as_slice(), clock) else {
+        anyhow::bail!(invalid());
+    };
+    anyhow::ensure!(
+        year >= 1970 && (1..=12).contains(&month) && (1..=31).contains(&day),
+        invalid()
+    );
+    anyhow::ensure!(hour < 24 && minute < 60, invalid());
+    // The date is checked to be after the epoch above
+    let days = days_since_epoch(year as i64, month as i64, day as i64) as u64;
+    Ok(days * 86400 + hour * 3600 + minute * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freeze_schedule() {
+        let windows =
+            parse_schedule("2024-03-01..2024-03-05T12:30, 1970-01-01..1970-01-02").unwrap();
+        assert_eq!(
+            windows,
+            [
+                FreezeWindow {
+                    start: 1709251200,
+                    end: 1709641800,
+                    until: "2024-03-05T12:30".to_string(),
+                },
+                FreezeWindow {
+                    start: 0,
+                    end: 86400,
+                    until: "1970-01-02".to_string(),
+                },
+            ]
+        );
+        assert_eq!(window_at(&windows, 1709251199), None);
+        assert_eq!(window_at(&windows, 1709251200), Some(&windows[0]));
+        assert_eq!(window_at(&windows, 1709641800), None);
+        assert_eq!(window_at(&windows, 86399), Some(&windows[1]));
+
+        assert!(parse_schedule("2024-03-01").is_err());
+        assert!(parse_schedule("2024-03-05..2024-03-01").is_err());
+        assert!(parse_schedule("2024-13-01..2024-14-01").is_err());
+        assert!(parse_schedule("2024-03-01T25:00..2024-03-02").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use log::info;
+use reqwest::blocking::Response;
+use reqwest::Method;
+use serde_json::Value;
+
+/// The fields of GitHub's responses that tell what a write resulted in
+const KEY_FIELDS: &[&str] = &[
+    "id",
+    "slug",
+    "name",
+    "login",
+    "state",
+    "role",
+    "permission",
+    "permissions",
+    "privacy",
+    "message",
+];
+
+/// Fields whose values are masked, matched as a substring of the field name
+const SECRET_FIELDS: &[&str] = &["token", "secret", "password", "key"];
+
+/// Logs a write along with the key fields of GitHub's response to it. The body of the response
+/// is read to do so, and a response with the same status, headers and body is returned in its
+/// place.
+pub(super) fn explain(
+    method: &Method,
+    url: &str,
+    body: Option<&Value>,
+    resp: Response,
+) -> reqwest::Result<Response> {
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let bytes = resp.bytes()?;
+
+    let request = match body {
+        // The query of a GraphQL mutation is the same for every call, only the variables differ
+        Some(body) => format!(" {}", masked(body.get("variables").unwrap_or(body).clone())),
+        None => String::new(),
+    };
+    let outcome = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => {
+            let mut fields = Vec::new();
+            key_fields(&value, &mut fields);
+            fields.join(", ")
+        }
+        Err(_) if bytes.is_empty() => "no content".to_string(),
+        Err(_) => "a body that isn't JSON".to_string(),
+    };
+    info!("requested {method} {url}{request}, GitHub returned {status}: {outcome}");
+
+    let mut rebuilt = http::Response::builder().status(status);
+    if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+        *rebuilt_headers = headers;
+    }
+    Ok(Response::from(
+        rebuilt
+            .body(bytes)
+            .expect("the status and headers are valid"),
+    ))
+}
+
+/// Collects the key fields of a response, as `path=value`. The fields of a REST response are only
+/// looked up at the top level, while the ones of a GraphQL response are looked up in all the
+/// objects of its `data`.
+fn key_fields(value: &Value, fields: &mut Vec<String>) {
+    let value = masked(value.clone());
+    if let Some(data) = value.get("data") {
+        return nested_key_fields(data, "data", fields);
+    }
+    for (name, value) in value.as_object().into_iter().flatten() {
+        if KEY_FIELDS.contains(&name.as_str()) && !value.is_object() && !value.is_array() {
+            fields.push(format!("{name}={value}"));
+        }
+    }
+}
+
+fn nested_key_fields(value: &Value, path: &str, fields: &mut Vec<String>) {
+    let Value::Object(object) = value else {
+        return;
+    };
+    for (name, value) in object {
+        let path = format!("{path}.{name}");
+        if value.is_object() {
+            nested_key_fields(value, &path, fields);
+        } else if KEY_FIELDS.contains(&name.as_str()) && !value.is_array() {
+            fields.push(format!("{path}={value}"));
+        }
+    }
+}
+
+/// Replaces the values of the fields that could hold secrets, at any depth
+fn masked(mut value: Value) -> Value {
+    match &mut value {
+        Value::Object(object) => {
+            for (name, field) in object.iter_mut() {
+                let name = name.to_lowercase();
+                if SECRET_FIELDS.iter().any(|secret| name.contains(secret)) {
+                    *field = Value::String("***".to_string());
+                } else {
+                    *field = masked(field.take());
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                *item = masked(item.take());
+            }
+        }
+        _ => {}
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_key_fields() {
+        let mut fields = Vec::new();
+        key_fields(
+            &json!({
+                "id": 42,
+                "slug": "admins",
+                "privacy": "closed",
+                "organization": {"id": 1, "login": "rust-lang"},
+                "url": "https://api.github.com/teams/42",
+            }),
+            &mut fields,
+        );
+        assert_eq!(fields, ["id=42", "privacy=\"closed\"", "slug=\"admins\""]);
+
+        let mut fields = Vec::new();
+        key_fields(
+            &json!({"data": {"createBranchProtectionRule": {"branchProtectionRule": {"id": "BPR_1"}}}}),
+            &mut fields,
+        );
+        assert_eq!(
+            fields,
+            ["data.createBranchProtectionRule.branchProtectionRule.id=\"BPR_1\""]
+        );
+
+        assert_eq!(
+            masked(json!({"name": "sync", "config": {"secret": "hunter2", "api_key": "abc"}})),
+            json!({"name": "sync", "config": {"secret": "***", "api_key": "***"}})
+        );
+    }
+}
@@ -1,3 +1,4 @@
+mod explain;
 mod read;
 mod write;
 
@@ -6,34 +7,65 @@ use anyhow::{bail, Context};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine as _;
 use hyper_old_types::header::{Link, RelationType};
-use log::{debug, trace};
+use log::{debug, info, trace};
 use reqwest::header::HeaderMap;
 use reqwest::{
     blocking::{Client, RequestBuilder, Response},
     header::{self, HeaderValue},
     Method, StatusCode,
 };
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) use read::{GitHubApiRead, GithubRead};
 pub(crate) use write::GitHubWrite;
 
+/// How many times a read failing because of the network or GitHub's servers is retried by default
+const DEFAULT_MAX_RETRIES: u32 = 3;
+/// How long to wait before the first retry of a read, doubled for each further retry
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How many times a request is sent again after waiting for a rate limit to reset
+const MAX_RATE_LIMIT_WAITS: u32 = 5;
+
 #[derive(Clone)]
 pub(crate) struct HttpClient {
     client: Client,
     base_url: String,
+    /// The tokens requests are spread across, the first one being the primary token
+    tokens: Arc<[HeaderValue]>,
+    /// The rate limit budget left for each token and rate limit, as last reported by GitHub
+    remaining: Arc<Mutex<HashMap<(usize, RateLimit), u64>>>,
+    /// Log each write along with the key fields of GitHub's response
+    explain: bool,
+    /// How many times a read failing because of the network or GitHub's servers is retried
+    max_retries: u32,
 }
 
 impl HttpClient {
-    pub(crate) fn from_url_and_token(mut base_url: String, token: String) -> anyhow::Result<Self> {
+    /// Create a client authenticating with a pool of tokens. Requests go through the token with
+    /// the most rate limit budget left, except for the writes, which all use the first token.
+    pub(crate) fn from_url_and_tokens(
+        mut base_url: String,
+        tokens: Vec<String>,
+    ) -> anyhow::Result<Self> {
+        if tokens.is_empty() {
+            bail!("at least one GitHub token is required");
+        }
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                let mut auth = HeaderValue::from_str(&format!("token {}", token))?;
+                auth.set_sensitive(true);
+                Ok(auth)
+            })
+            .collect::<anyhow::Result<Arc<[_]>>>()?;
+
         let mut builder = reqwest::blocking::ClientBuilder::default();
         let mut map = HeaderMap::default();
-        let mut auth = HeaderValue::from_str(&format!("token {}", token))?;
-        auth.set_sensitive(true);
-
-        map.insert(header::AUTHORIZATION, auth);
         map.insert(
             header::USER_AGENT,
             HeaderValue::from_static(crate::USER_AGENT),
@@ -47,26 +79,103 @@ impl HttpClient {
         Ok(Self {
             client: builder.build()?,
             base_url,
+            remaining: Arc::new(Mutex::new(HashMap::new())),
+            tokens,
+            explain: false,
+            max_retries: DEFAULT_MAX_RETRIES,
         })
     }
 
-    fn req(&self, method: Method, url: &str) -> anyhow::Result<RequestBuilder> {
+    /// Retry reads failing because of the network or GitHub's servers up to `max_retries` times
+    pub(crate) fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Log each write made through the client, with what GitHub returned for it
+    pub(crate) fn with_explain(mut self) -> Self {
+        self.explain = true;
+        self
+    }
+
+    fn req(&self, method: Method, url: &str) -> anyhow::Result<PooledRequest<'_>> {
+        self.req_with_token(self.pick_token(RateLimit::of(url)), method, url)
+    }
+
+    /// Like `req`, but always authenticates with the primary token, for the writes and the other
+    /// requests that act on behalf of a specific identity.
+    fn req_primary(&self, method: Method, url: &str) -> anyhow::Result<PooledRequest<'_>> {
+        self.req_with_token(0, method, url)
+    }
+
+    fn req_with_token(
+        &self,
+        token: usize,
+        method: Method,
+        url: &str,
+    ) -> anyhow::Result<PooledRequest<'_>> {
         let url = if url.starts_with("https://") {
             Cow::Borrowed(url)
         } else {
             Cow::Owned(format!("{}{url}", self.base_url))
         };
-        trace!("http request: {} {}", method, url);
-        Ok(self.client.request(method, url.as_ref()))
+        trace!("http request: {} {} (token #{})", method, url, token);
+        let builder = self
+            .client
+            .request(method.clone(), url.as_ref())
+            .header(header::AUTHORIZATION, self.tokens[token].clone());
+        Ok(PooledRequest {
+            client: self,
+            token,
+            rate_limit: RateLimit::of(&url),
+            builder,
+            method,
+            url: url.into_owned(),
+            body: None,
+        })
+    }
+
+    /// The token with the most budget left in `rate_limit`. Tokens that weren't used yet for it
+    /// are preferred, and ties go to the earliest token.
+    fn pick_token(&self, rate_limit: RateLimit) -> usize {
+        let remaining = self.remaining.lock().unwrap();
+        let budget = |token: usize| {
+            remaining
+                .get(&(token, rate_limit))
+                .copied()
+                .unwrap_or(u64::MAX)
+        };
+        (0..self.tokens.len()).fold(0, |best, token| {
+            if budget(token) > budget(best) {
+                token
+            } else {
+                best
+            }
+        })
+    }
+
+    fn record_remaining(&self, token: usize, rate_limit: RateLimit, resp: &Response) {
+        let remaining = resp
+            .headers()
+            .get("x-ratelimit-remaining")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        if let Some(remaining) = remaining {
+            self.remaining
+                .lock()
+                .unwrap()
+                .insert((token, rate_limit), remaining);
+        }
     }
 
+    /// Sends a write with the primary token
     fn send<T: serde::Serialize + std::fmt::Debug>(
         &self,
         method: Method,
         url: &str,
         body: &T,
     ) -> Result<Response, anyhow::Error> {
-        let resp = self.req(method, url)?.json(body).send()?;
+        let resp = self.req_primary(method, url)?.json(body).send()?;
         resp.custom_error_for_status()
     }
 
@@ -86,6 +195,28 @@ impl HttpClient {
     }
 
     fn graphql<R, V>(&self, query: &str, variables: V) -> anyhow::Result<R>
+    where
+        R: serde::de::DeserializeOwned,
+        V: serde::Serialize,
+    {
+        self.graphql_with(self.req(Method::POST, "graphql")?, query, variables)
+    }
+
+    /// Like `graphql`, but always authenticates with the primary token, for the mutations
+    fn graphql_primary<R, V>(&self, query: &str, variables: V) -> anyhow::Result<R>
+    where
+        R: serde::de::DeserializeOwned,
+        V: serde::Serialize,
+    {
+        self.graphql_with(self.req_primary(Method::POST, "graphql")?, query, variables)
+    }
+
+    fn graphql_with<R, V>(
+        &self,
+        req: PooledRequest<'_>,
+        query: &str,
+        variables: V,
+    ) -> anyhow::Result<R>
     where
         R: serde::de::DeserializeOwned,
         V: serde::Serialize,
@@ -95,8 +226,7 @@ impl HttpClient {
             query: &'a str,
             variables: V,
         }
-        let resp = self
-            .req(Method::POST, "graphql")?
+        let resp = req
             .json(&Request { query, variables })
             .send()?
             .custom_error_for_status()?;
@@ -148,6 +278,147 @@ impl HttpClient {
     }
 }
 
+/// A request authenticated with one of the tokens of the pool, that records the rate limit budget
+/// left for that token once sent.
+struct PooledRequest<'a> {
+    client: &'a HttpClient,
+    token: usize,
+    rate_limit: RateLimit,
+    builder: RequestBuilder,
+    method: Method,
+    url: String,
+    /// The JSON body of the request, if any
+    body: Option<serde_json::Value>,
+}
+
+impl PooledRequest<'_> {
+    fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        self.builder = self.builder.json(body);
+        self.body = serde_json::to_value(body).ok();
+        self
+    }
+
+    fn send(self) -> reqwest::Result<Response> {
+        let is_write = is_write(&self.method, &self.url, self.body.as_ref());
+        // Writes aren't retried, as a write that failed could still have been applied
+        let retries = if is_write { 0 } else { self.client.max_retries };
+        let mut waits = 0;
+        let resp = loop {
+            let resp = retry_transient_failures(retries, RETRY_DELAY, || {
+                let builder = self
+                    .builder
+                    .try_clone()
+                    .expect("requests with a JSON body can be cloned");
+                let resp = builder.send()?;
+                self.client
+                    .record_remaining(self.token, self.rate_limit, &resp);
+                Ok(resp)
+            })?;
+            // Rate limited requests weren't processed, so even writes can be sent again
+            match rate_limit_wait(&resp, SystemTime::now()) {
+                Some(wait) if waits < MAX_RATE_LIMIT_WAITS => {
+                    waits += 1;
+                    info!(
+                        "rate limited by GitHub on {} {}, waiting {wait:?} ({waits}/{MAX_RATE_LIMIT_WAITS})",
+                        self.method, self.url
+                    );
+                    std::thread::sleep(wait);
+                }
+                _ => break resp,
+            }
+        };
+        if self.client.explain && is_write {
+            return explain::explain(&self.method, &self.url, self.body.as_ref(), resp);
+        }
+        Ok(resp)
+    }
+}
+
+/// GitHub keeps separate rate limit budgets for each token: one for the REST API, and one for
+/// the GraphQL API
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum RateLimit {
+    Rest,
+    Graphql,
+}
+
+impl RateLimit {
+    fn of(url: &str) -> Self {
+        if url.ends_with("graphql") {
+            RateLimit::Graphql
+        } else {
+            RateLimit::Rest
+        }
+    }
+}
+
+/// How long to wait before sending a request again, when GitHub rejected it because of its primary
+/// or secondary rate limit
+fn rate_limit_wait(resp: &Response, now: SystemTime) -> Option<Duration> {
+    if !matches!(
+        resp.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+    let header = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+    };
+    if let Some(seconds) = header("retry-after") {
+        return Some(Duration::from_secs(seconds));
+    }
+    if header("x-ratelimit-remaining") == Some(0) {
+        let reset = UNIX_EPOCH + Duration::from_secs(header("x-ratelimit-reset")?);
+        // Wait a bit past the reset, so that the clocks don't need to be in sync
+        let wait = reset.duration_since(now).unwrap_or_default();
+        return Some(wait + Duration::from_secs(1));
+    }
+    None
+}
+
+/// Whether a request changes something on GitHub. GraphQL requests are always a `POST`, so only
+/// the mutations among them count.
+fn is_write(method: &Method, url: &str, body: Option<&serde_json::Value>) -> bool {
+    if *method == Method::GET {
+        return false;
+    }
+    if url.ends_with("graphql") {
+        return body
+            .and_then(|b| b.get("query"))
+            .and_then(|q| q.as_str())
+            .is_some_and(|q| q.trim_start().starts_with("mutation"));
+    }
+    true
+}
+
+/// Sends a request with `send`, retrying it up to `retries` times while it fails because of the
+/// network or a server error. The delay between attempts starts at `delay` and doubles each time.
+fn retry_transient_failures(
+    retries: u32,
+    mut delay: Duration,
+    mut send: impl FnMut() -> reqwest::Result<Response>,
+) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let result = send();
+        let failure = match &result {
+            Ok(resp) if resp.status().is_server_error() => resp.status().to_string(),
+            Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => err.to_string(),
+            _ => return result,
+        };
+        if attempt == retries {
+            return result;
+        }
+        attempt += 1;
+        log::info!("retrying a GitHub request in {delay:?} ({attempt}/{retries}): {failure}");
+        std::thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
 fn allow_not_found(resp: Response, method: Method, url: &str) -> Result<(), anyhow::Error> {
     match resp.status() {
         StatusCode::NOT_FOUND => {
@@ -209,6 +480,9 @@ pub(crate) struct Team {
     /// The slug usually matches the name but can differ.
     /// For example, a team named rustup.rs would have a slug rustup-rs.
     pub(crate) slug: String,
+    /// When the team was last modified, used to detect changes made after the plan was computed.
+    #[serde(default)]
+    pub(crate) updated_at: Option<String>,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -238,6 +512,19 @@ pub(crate) enum RepoPermission {
     Read,
 }
 
+impl RepoPermission {
+    /// Orders the permissions by the access they give, as they are a superset of each other
+    pub(crate) fn rank(&self) -> u8 {
+        match self {
+            Self::Read => 0,
+            Self::Triage => 1,
+            Self::Write => 2,
+            Self::Maintain => 3,
+            Self::Admin => 4,
+        }
+    }
+}
+
 impl fmt::Display for RepoPermission {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -275,6 +562,9 @@ pub(crate) struct Repo {
     pub(crate) archived: bool,
     #[serde(default)]
     pub(crate) allow_auto_merge: Option<bool>,
+    /// When the repo was last modified, used to detect changes made after the plan was computed.
+    #[serde(default)]
+    pub(crate) updated_at: Option<String>,
 }
 
 fn repo_owner<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -286,11 +576,19 @@ where
 }
 
 /// An object with a `login` field
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Login {
     pub(crate) login: String,
 }
 
+/// A pending invitation to join a team
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct TeamInvitation {
+    pub(crate) login: String,
+    #[serde(default)]
+    pub(crate) created_at: Option<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Eq, PartialEq, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum TeamPrivacy {
@@ -301,7 +599,10 @@ pub(crate) enum TeamPrivacy {
 #[derive(serde::Serialize, serde::Deserialize, Debug, Eq, PartialEq, Copy, Clone)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "SCREAMING_SNAKE_CASE"))]
 pub(crate) enum TeamRole {
+    // The snake case names are what's serialized, for example in the transaction log
+    #[serde(alias = "member")]
     Member,
+    #[serde(alias = "maintainer")]
     Maintainer,
 }
 
@@ -328,7 +629,7 @@ fn team_node_id(id: u64) -> String {
     BASE64_STANDARD.encode(format!("04:Team{id}"))
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct BranchProtection {
     pub(crate) pattern: String,
@@ -369,7 +670,7 @@ where
 }
 
 /// Entities that can be allowed to push to a branch in a repo
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(untagged)]
 pub(crate) enum PushAllowanceActor {
     User(UserPushAllowanceActor),
@@ -377,13 +678,13 @@ pub(crate) enum PushAllowanceActor {
 }
 
 /// User who can be allowed to push to a branch in a repo
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct UserPushAllowanceActor {
     pub(crate) login: String,
 }
 
 /// Team that can be allowed to push to a branch in a repo
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct TeamPushAllowanceActor {
     pub(crate) organization: Login,
     pub(crate) name: String,
@@ -394,10 +695,139 @@ pub(crate) enum BranchProtectionOp {
     UpdateBranchProtection(String),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
 pub(crate) struct RepoSettings {
     pub description: Option<String>,
     pub homepage: Option<String>,
     pub archived: bool,
     pub auto_merge_enabled: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_token() {
+        let client = HttpClient::from_url_and_tokens(
+            "https://api.github.com/".to_string(),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        )
+        .unwrap();
+        assert_eq!(client.pick_token(RateLimit::Rest), 0);
+
+        let set = |budgets: &[(usize, RateLimit, u64)]| {
+            *client.remaining.lock().unwrap() = budgets
+                .iter()
+                .map(|(token, limit, budget)| ((*token, *limit), *budget))
+                .collect();
+        };
+        set(&[(0, RateLimit::Rest, 100), (2, RateLimit::Rest, 200)]);
+        assert_eq!(client.pick_token(RateLimit::Rest), 1);
+
+        set(&[
+            (0, RateLimit::Rest, 100),
+            (1, RateLimit::Rest, 50),
+            (2, RateLimit::Rest, 200),
+        ]);
+        assert_eq!(client.pick_token(RateLimit::Rest), 2);
+
+        set(&[
+            (0, RateLimit::Rest, 200),
+            (1, RateLimit::Rest, 50),
+            (2, RateLimit::Rest, 200),
+        ]);
+        assert_eq!(client.pick_token(RateLimit::Rest), 0);
+
+        // The GraphQL budgets are kept apart from the REST ones
+        set(&[
+            (0, RateLimit::Rest, 5000),
+            (0, RateLimit::Graphql, 10),
+            (1, RateLimit::Rest, 10),
+            (1, RateLimit::Graphql, 5000),
+            (2, RateLimit::Rest, 10),
+            (2, RateLimit::Graphql, 10),
+        ]);
+        assert_eq!(client.pick_token(RateLimit::Rest), 0);
+        assert_eq!(client.pick_token(RateLimit::Graphql), 1);
+    }
+
+    #[test]
+    fn test_is_write() {
+        let url = "https://api.github.com/graphql";
+        let query = serde_json::json!({"query": "query { viewer { id } }"});
+        assert!(!is_write(&Method::POST, url, Some(&query)));
+        let mutation = serde_json::json!({"query": "\n mutation($id: ID!) {}"});
+        assert!(is_write(&Method::POST, url, Some(&mutation)));
+        let url = "https://api.github.com/orgs/rust-lang/teams/admins";
+        assert!(is_write(&Method::DELETE, url, None));
+        assert!(!is_write(&Method::GET, url, None));
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let response = |status: u16, headers: &[(&str, &str)]| {
+            let mut builder = http::Response::builder().status(status);
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            Response::from(builder.body(String::new()).unwrap())
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        let resp = response(403, &[("retry-after", "60")]);
+        assert_eq!(rate_limit_wait(&resp, now), Some(Duration::from_secs(60)));
+        let resp = response(429, &[("retry-after", "5")]);
+        assert_eq!(rate_limit_wait(&resp, now), Some(Duration::from_secs(5)));
+
+        let reset = [
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1030"),
+        ];
+        let resp = response(403, &reset);
+        assert_eq!(rate_limit_wait(&resp, now), Some(Duration::from_secs(31)));
+        let reset = [("x-ratelimit-remaining", "0"), ("x-ratelimit-reset", "900")];
+        let resp = response(403, &reset);
+        assert_eq!(rate_limit_wait(&resp, now), Some(Duration::from_secs(1)));
+
+        // Forbidden for another reason than the rate limit
+        let resp = response(403, &[("x-ratelimit-remaining", "4000")]);
+        assert_eq!(rate_limit_wait(&resp, now), None);
+        let resp = response(200, &[("retry-after", "60")]);
+        assert_eq!(rate_limit_wait(&resp, now), None);
+    }
+
+    #[test]
+    fn test_retry_transient_failures() {
+        let response = |status: u16| {
+            Response::from(
+                http::Response::builder()
+                    .status(status)
+                    .body(String::new())
+                    .unwrap(),
+            )
+        };
+
+        let mut statuses = vec![200, 503, 502];
+        let result =
+            retry_transient_failures(3, Duration::ZERO, || Ok(response(statuses.pop().unwrap())));
+        assert_eq!(result.unwrap().status(), StatusCode::OK);
+        assert!(statuses.is_empty());
+
+        let mut sent = 0;
+        let result = retry_transient_failures(2, Duration::ZERO, || {
+            sent += 1;
+            Ok(response(500))
+        });
+        assert_eq!(result.unwrap().status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(sent, 3);
+
+        let mut sent = 0;
+        let result = retry_transient_failures(3, Duration::ZERO, || {
+            sent += 1;
+            Ok(response(404))
+        });
+        assert_eq!(result.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(sent, 1);
+    }
+}
@@ -1,18 +1,33 @@
 use crate::github::api::{
     team_node_id, user_node_id, BranchProtection, GraphNode, GraphNodes, GraphPageInfo, HttpClient,
-    Login, OrgAppInstallation, Repo, RepoAppInstallation, RepoTeam, RepoUser, Team, TeamMember,
-    TeamRole,
+    Login, OrgAppInstallation, Repo, RepoAppInstallation, RepoPermission, RepoTeam, RepoUser, Team,
+    TeamInvitation, TeamMember, TeamRole,
 };
-use reqwest::Method;
+use crate::utils::ResponseExt;
+use log::warn;
+use reqwest::{Method, StatusCode};
 use std::collections::{HashMap, HashSet};
 
-pub(crate) trait GithubRead {
+/// Reads can happen from several threads at once, like the repos diffed concurrently
+pub(crate) trait GithubRead: Sync {
     /// Get user names by user ids
     fn usernames(&self, ids: &[u64]) -> anyhow::Result<HashMap<u64, String>>;
 
+    /// Get the id of a user by login, if the user exists
+    fn user_id(&self, login: &str) -> anyhow::Result<Option<u64>>;
+
     /// Get the owners of an org
     fn org_owners(&self, org: &str) -> anyhow::Result<HashSet<u64>>;
 
+    /// The members of an org, with their emails in the domains verified by the org
+    fn org_members_verified_emails(
+        &self,
+        org: &str,
+    ) -> anyhow::Result<HashMap<String, Vec<String>>>;
+
+    /// The domains added to an org that are still pending verification
+    fn org_unverified_domains(&self, org: &str) -> anyhow::Result<Vec<String>>;
+
     /// Get the app installations of an org
     fn org_app_installations(&self, org: &str) -> anyhow::Result<Vec<OrgAppInstallation>>;
 
@@ -24,17 +39,29 @@ pub(crate) trait GithubRead {
 
     /// Get all teams associated with a org
     ///
-    /// Returns a list of tuples of team name and slug
-    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String)>>;
+    /// Returns a list of tuples of team name, slug and the name of its parent team, if any
+    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String, Option<String>)>>;
 
     /// Get the team by name and org
     fn team(&self, org: &str, team: &str) -> anyhow::Result<Option<Team>>;
 
     fn team_memberships(&self, team: &Team) -> anyhow::Result<HashMap<u64, TeamMember>>;
 
-    /// The GitHub names of users invited to the given team
-    fn team_membership_invitations(&self, org: &str, team: &str)
-        -> anyhow::Result<HashSet<String>>;
+    /// The GitHub names of users invited to the given team, with when they were invited
+    fn team_membership_invitations(
+        &self,
+        org: &str,
+        team: &str,
+    ) -> anyhow::Result<HashMap<String, Option<String>>>;
+
+    /// The GitHub names of users invited to join the given org, with when they were invited
+    fn org_invitations(&self, org: &str) -> anyhow::Result<HashMap<String, Option<String>>>;
+
+    /// Whether the user is a member of the given org
+    fn org_membership(&self, org: &str, login: &str) -> anyhow::Result<bool>;
+
+    /// Get all the repos of an org
+    fn org_repos(&self, org: &str) -> anyhow::Result<Vec<Repo>>;
 
     /// Get a repo by org and name
     fn repo(&self, org: &str, repo: &str) -> anyhow::Result<Option<Repo>>;
@@ -47,6 +74,10 @@ pub(crate) trait GithubRead {
     /// Only fetches those who are direct collaborators (i.e., not a collaborator through a repo team)
     fn repo_collaborators(&self, org: &str, repo: &str) -> anyhow::Result<Vec<RepoUser>>;
 
+    /// Get the users invited to collaborate on a repo who didn't accept yet, with the permission
+    /// they were invited with
+    fn repo_invitations(&self, org: &str, repo: &str) -> anyhow::Result<Vec<RepoUser>>;
+
     /// Get branch_protections
     /// Returns a map branch pattern -> (protection ID, protection data)
     fn branch_protections(
@@ -54,6 +85,19 @@ pub(crate) trait GithubRead {
         org: &str,
         repo: &str,
     ) -> anyhow::Result<HashMap<String, (String, BranchProtection)>>;
+
+    /// The community health files GitHub found in a public repo (e.g. `code_of_conduct` or
+    /// `issue_template`), or `None` if the repo has no community profile
+    fn repo_health_files(&self, org: &str, repo: &str) -> anyhow::Result<Option<HashSet<String>>>;
+
+    /// Whether an existing repo has a security policy, either its own or the org default
+    fn repo_has_security_policy(&self, org: &str, repo: &str) -> anyhow::Result<bool>;
+
+    /// The names of the IdP groups a team's membership is synchronized from, if any
+    fn team_idp_groups(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>>;
+
+    /// The logins of the accounts owning the tokens the sync authenticates with
+    fn token_owners(&self) -> anyhow::Result<Vec<String>>;
 }
 
 pub(crate) struct GitHubApiRead {
@@ -104,6 +148,18 @@ impl GithubRead for GitHubApiRead {
         Ok(result)
     }
 
+    fn user_id(&self, login: &str) -> anyhow::Result<Option<u64>> {
+        #[derive(serde::Deserialize)]
+        struct User {
+            id: u64,
+        }
+
+        let user: Option<User> = self
+            .client
+            .send_option(Method::GET, &format!("users/{login}"))?;
+        Ok(user.map(|user| user.id))
+    }
+
     fn org_owners(&self, org: &str) -> anyhow::Result<HashSet<u64>> {
         #[derive(serde::Deserialize, Eq, PartialEq, Hash)]
         struct User {
@@ -121,6 +177,139 @@ impl GithubRead for GitHubApiRead {
         Ok(owners)
     }
 
+    fn org_members_verified_emails(
+        &self,
+        org: &str,
+    ) -> anyhow::Result<HashMap<String, Vec<String>>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RespOrg {
+            members_with_role: RespMembers,
+        }
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RespMembers {
+            page_info: GraphPageInfo,
+            nodes: Vec<RespNode>,
+        }
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RespNode {
+            login: String,
+            organization_verified_domain_emails: Vec<String>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Resp {
+            organization: Option<RespOrg>,
+        }
+        #[derive(serde::Serialize)]
+        struct Params<'a> {
+            org: &'a str,
+            cursor: Option<&'a str>,
+        }
+        static QUERY: &str = "
+            query($org: String!, $cursor: String) {
+                organization(login: $org) {
+                    membersWithRole(first: 100, after: $cursor) {
+                        pageInfo {
+                            endCursor
+                            hasNextPage
+                        }
+                        nodes {
+                            login
+                            organizationVerifiedDomainEmails(login: $org)
+                        }
+                    }
+                }
+            }
+        ";
+
+        let mut members = HashMap::new();
+        let mut page_info = GraphPageInfo::start();
+        while page_info.has_next_page {
+            let res: Resp = self.client.graphql(
+                QUERY,
+                Params {
+                    org,
+                    cursor: page_info.end_cursor.as_deref(),
+                },
+            )?;
+            let Some(organization) = res.organization else {
+                break;
+            };
+            page_info = organization.members_with_role.page_info;
+            for node in organization.members_with_role.nodes {
+                members.insert(node.login, node.organization_verified_domain_emails);
+            }
+        }
+        Ok(members)
+    }
+
+    fn org_unverified_domains(&self, org: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        struct RespOrg {
+            domains: RespDomains,
+        }
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RespDomains {
+            page_info: GraphPageInfo,
+            nodes: Vec<RespNode>,
+        }
+        #[derive(serde::Deserialize)]
+        struct RespNode {
+            domain: String,
+        }
+        #[derive(serde::Deserialize)]
+        struct Resp {
+            organization: Option<RespOrg>,
+        }
+        #[derive(serde::Serialize)]
+        struct Params<'a> {
+            org: &'a str,
+            cursor: Option<&'a str>,
+        }
+        static QUERY: &str = "
+            query($org: String!, $cursor: String) {
+                organization(login: $org) {
+                    domains(first: 100, after: $cursor, isVerified: false) {
+                        pageInfo {
+                            endCursor
+                            hasNextPage
+                        }
+                        nodes {
+                            domain
+                        }
+                    }
+                }
+            }
+        ";
+
+        let mut domains = Vec::new();
+        let mut page_info = GraphPageInfo::start();
+        while page_info.has_next_page {
+            let res: Resp = self.client.graphql(
+                QUERY,
+                Params {
+                    org,
+                    cursor: page_info.end_cursor.as_deref(),
+                },
+            )?;
+            let Some(organization) = res.organization else {
+                break;
+            };
+            page_info = organization.domains.page_info;
+            domains.extend(
+                organization
+                    .domains
+                    .nodes
+                    .into_iter()
+                    .map(|node| node.domain),
+            );
+        }
+        Ok(domains)
+    }
+
     fn org_app_installations(&self, org: &str) -> anyhow::Result<Vec<OrgAppInstallation>> {
         #[derive(serde::Deserialize, Debug)]
         struct InstallationPage {
@@ -160,14 +349,28 @@ impl GithubRead for GitHubApiRead {
         Ok(installations)
     }
 
-    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String)>> {
+    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String, Option<String>)>> {
+        #[derive(serde::Deserialize)]
+        struct OrgTeam {
+            name: String,
+            slug: String,
+            parent: Option<Parent>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Parent {
+            name: String,
+        }
+
         let mut teams = Vec::new();
 
         self.client.rest_paginated(
             &Method::GET,
             format!("orgs/{org}/teams"),
-            |resp: Vec<Team>| {
-                teams.extend(resp.into_iter().map(|t| (t.name, t.slug)));
+            |resp: Vec<OrgTeam>| {
+                teams.extend(
+                    resp.into_iter()
+                        .map(|t| (t.name, t.slug, t.parent.map(|p| p.name))),
+                );
                 Ok(())
             },
         )?;
@@ -263,14 +466,39 @@ impl GithubRead for GitHubApiRead {
         &self,
         org: &str,
         team: &str,
-    ) -> anyhow::Result<HashSet<String>> {
-        let mut invites = HashSet::new();
+    ) -> anyhow::Result<HashMap<String, Option<String>>> {
+        let mut invites = HashMap::new();
 
         self.client.rest_paginated(
             &Method::GET,
             format!("orgs/{org}/teams/{team}/invitations"),
-            |resp: Vec<Login>| {
-                invites.extend(resp.into_iter().map(|l| l.login));
+            |resp: Vec<TeamInvitation>| {
+                invites.extend(resp.into_iter().map(|i| (i.login, i.created_at)));
+                Ok(())
+            },
+        )?;
+
+        Ok(invites)
+    }
+
+    fn org_invitations(&self, org: &str) -> anyhow::Result<HashMap<String, Option<String>>> {
+        #[derive(serde::Deserialize)]
+        struct Invitation {
+            // Invitations sent to an email address have no login yet
+            login: Option<String>,
+            #[serde(default)]
+            created_at: Option<String>,
+        }
+
+        let mut invites = HashMap::new();
+        self.client.rest_paginated(
+            &Method::GET,
+            format!("orgs/{org}/invitations"),
+            |resp: Vec<Invitation>| {
+                invites.extend(
+                    resp.into_iter()
+                        .filter_map(|i| Some((i.login?, i.created_at))),
+                );
                 Ok(())
             },
         )?;
@@ -278,6 +506,33 @@ impl GithubRead for GitHubApiRead {
         Ok(invites)
     }
 
+    fn org_membership(&self, org: &str, login: &str) -> anyhow::Result<bool> {
+        let resp = self
+            .client
+            .req(Method::GET, &format!("orgs/{org}/members/{login}"))?
+            .send()?;
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(resp.custom_error_for_status().unwrap_err()),
+        }
+    }
+
+    fn org_repos(&self, org: &str) -> anyhow::Result<Vec<Repo>> {
+        let mut repos = Vec::new();
+
+        self.client.rest_paginated(
+            &Method::GET,
+            format!("orgs/{org}/repos"),
+            |resp: Vec<Repo>| {
+                repos.extend(resp);
+                Ok(())
+            },
+        )?;
+
+        Ok(repos)
+    }
+
     fn repo(&self, org: &str, repo: &str) -> anyhow::Result<Option<Repo>> {
         self.client
             .send_option(Method::GET, &format!("repos/{org}/{repo}"))
@@ -313,6 +568,32 @@ impl GithubRead for GitHubApiRead {
         Ok(users)
     }
 
+    fn repo_invitations(&self, org: &str, repo: &str) -> anyhow::Result<Vec<RepoUser>> {
+        #[derive(serde::Deserialize)]
+        struct Invitation {
+            // Invitations sent to an email address have no invitee yet
+            invitee: Option<Login>,
+            permissions: RepoPermission,
+        }
+
+        let mut users = Vec::new();
+        self.client.rest_paginated(
+            &Method::GET,
+            format!("repos/{org}/{repo}/invitations"),
+            |resp: Vec<Invitation>| {
+                users.extend(resp.into_iter().filter_map(|invitation| {
+                    Some(RepoUser {
+                        name: invitation.invitee?.login,
+                        permission: invitation.permissions,
+                    })
+                }));
+                Ok(())
+            },
+        )?;
+
+        Ok(users)
+    }
+
     fn branch_protections(
         &self,
         org: &str,
@@ -386,4 +667,103 @@ impl GithubRead for GitHubApiRead {
         }
         Ok(result)
     }
+
+    fn repo_health_files(&self, org: &str, repo: &str) -> anyhow::Result<Option<HashSet<String>>> {
+        #[derive(serde::Deserialize)]
+        struct Profile {
+            // Files that weren't found are null
+            files: HashMap<String, Option<serde::de::IgnoredAny>>,
+        }
+
+        let profile: Option<Profile> = self.client.send_option(
+            Method::GET,
+            &format!("repos/{org}/{repo}/community/profile"),
+        )?;
+        Ok(profile.map(|profile| {
+            profile
+                .files
+                .into_iter()
+                .filter(|(_, file)| file.is_some())
+                .map(|(name, _)| name)
+                .collect()
+        }))
+    }
+
+    fn repo_has_security_policy(&self, org: &str, repo: &str) -> anyhow::Result<bool> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RespRepo {
+            is_security_policy_enabled: Option<bool>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Resp {
+            repository: Option<RespRepo>,
+        }
+        #[derive(serde::Serialize)]
+        struct Params<'a> {
+            org: &'a str,
+            repo: &'a str,
+        }
+        static QUERY: &str = "
+            query($org: String!, $repo: String!) {
+                repository(owner: $org, name: $repo) {
+                    isSecurityPolicyEnabled
+                }
+            }
+        ";
+
+        let res: Resp = self.client.graphql(QUERY, Params { org, repo })?;
+        Ok(res
+            .repository
+            .and_then(|repo| repo.is_security_policy_enabled)
+            .unwrap_or(false))
+    }
+
+    fn team_idp_groups(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        struct Mappings {
+            #[serde(default)]
+            groups: Vec<Group>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Group {
+            group_name: String,
+        }
+
+        let url = format!("orgs/{org}/teams/{team}/team-sync/group-mappings");
+        let resp = self.client.req(Method::GET, &url)?.send()?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(Vec::new()),
+            // Returned for orgs without team synchronization, whose teams can't be mapped, but
+            // also when the token lacks the permissions to read the mappings
+            StatusCode::FORBIDDEN => {
+                let body = resp.text()?;
+                if !body.to_lowercase().contains("team synchronization") {
+                    warn!(
+                        "cannot read the IdP groups of {org}/{team}, treating it as not externally \
+                         managed: {body}"
+                    );
+                }
+                Ok(Vec::new())
+            }
+            _ => {
+                let mappings: Mappings = resp.custom_error_for_status()?.json_annotated()?;
+                Ok(mappings.groups.into_iter().map(|g| g.group_name).collect())
+            }
+        }
+    }
+
+    fn token_owners(&self) -> anyhow::Result<Vec<String>> {
+        let mut owners = Vec::new();
+        for token in 0..self.client.tokens.len() {
+            let user: Login = self
+                .client
+                .req_with_token(token, Method::GET, "user")?
+                .send()?
+                .custom_error_for_status()?
+                .json_annotated()?;
+            owners.push(user.login);
+        }
+        Ok(owners)
+    }
 }
@@ -1,16 +1,25 @@
 use log::debug;
 use reqwest::Method;
+use std::time::Duration;
 
 use crate::github::api::{
     allow_not_found, BranchProtection, BranchProtectionOp, HttpClient, Login, PushAllowanceActor,
     Repo, RepoPermission, RepoSettings, Team, TeamPrivacy, TeamPushAllowanceActor, TeamRole,
     UserPushAllowanceActor,
 };
+use crate::github::audit::{AuditEntry, AuditLog};
+use crate::github::transactions::{Transaction, TransactionLog};
 use crate::utils::ResponseExt;
 
 pub(crate) struct GitHubWrite {
     client: HttpClient,
     dry_run: bool,
+    /// How long to wait after adding a member to a team
+    membership_add_delay: Option<Duration>,
+    /// Where the applied membership changes are recorded
+    audit_log: Option<AuditLog>,
+    /// Where the applied writes to teams are recorded for replay
+    transaction_log: Option<TransactionLog>,
 }
 
 impl GitHubWrite {
@@ -18,9 +27,47 @@ impl GitHubWrite {
         Ok(Self {
             client: client.clone(),
             dry_run,
+            membership_add_delay: None,
+            audit_log: None,
+            transaction_log: None,
         })
     }
 
+    /// Pace team member additions, so that adding many members at once doesn't cause a burst of
+    /// notifications.
+    pub(crate) fn with_membership_add_delay(mut self, delay: Duration) -> Self {
+        self.membership_add_delay = Some(delay);
+        self
+    }
+
+    /// Record the membership changes applied to GitHub in an audit log
+    pub(crate) fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Record an applied membership change in the audit log, if there's one
+    pub(crate) fn audit(&self, entry: &AuditEntry<'_>) -> anyhow::Result<()> {
+        match &self.audit_log {
+            Some(audit_log) if !self.dry_run => audit_log.record(entry),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the writes applied to teams in a transaction log, so that they can be replayed
+    pub(crate) fn with_transaction_log(mut self, transaction_log: TransactionLog) -> Self {
+        self.transaction_log = Some(transaction_log);
+        self
+    }
+
+    /// Record an applied write to a team in the transaction log, if there's one
+    fn record(&self, transaction: impl FnOnce() -> Transaction) -> anyhow::Result<()> {
+        match &self.transaction_log {
+            Some(transaction_log) if !self.dry_run => transaction_log.record(&transaction()),
+            _ => Ok(()),
+        }
+    }
+
     fn user_id(&self, name: &str) -> anyhow::Result<String> {
         #[derive(serde::Serialize)]
         struct Params<'a> {
@@ -78,6 +125,22 @@ impl GitHubWrite {
         Ok(data.organization.team.id)
     }
 
+    /// Get when a team was last modified, if it exists
+    pub(crate) fn team_updated_at(&self, org: &str, name: &str) -> anyhow::Result<Option<String>> {
+        let team: Option<Team> = self
+            .client
+            .send_option(Method::GET, &format!("orgs/{org}/teams/{name}"))?;
+        Ok(team.and_then(|t| t.updated_at))
+    }
+
+    /// Get when a repo was last modified, if it exists
+    pub(crate) fn repo_updated_at(&self, org: &str, repo: &str) -> anyhow::Result<Option<String>> {
+        let repo: Option<Repo> = self
+            .client
+            .send_option(Method::GET, &format!("repos/{org}/{repo}"))?;
+        Ok(repo.and_then(|r| r.updated_at))
+    }
+
     /// Create a team in a org
     pub(crate) fn create_team(
         &self,
@@ -102,6 +165,7 @@ impl GitHubWrite {
                 description: Some(description.to_string()),
                 privacy,
                 slug: name.to_string(),
+                updated_at: None,
             })
         } else {
             let body = &Req {
@@ -109,10 +173,17 @@ impl GitHubWrite {
                 description,
                 privacy,
             };
-            Ok(self
+            let team = self
                 .client
                 .send(Method::POST, &format!("orgs/{org}/teams"), body)?
-                .json_annotated()?)
+                .json_annotated()?;
+            self.record(|| Transaction::CreateTeam {
+                org: org.to_string(),
+                name: name.to_string(),
+                description: description.to_string(),
+                privacy,
+            })?;
+            Ok(team)
         }
     }
 
@@ -146,23 +217,87 @@ impl GitHubWrite {
         if !self.dry_run {
             self.client
                 .send(Method::PATCH, &format!("orgs/{org}/teams/{name}"), &req)?;
+            self.record(|| Transaction::EditTeam {
+                org: org.to_string(),
+                name: name.to_string(),
+                new_name: new_name.map(str::to_string),
+                new_description: new_description.map(str::to_string),
+                new_privacy,
+            })?;
         }
 
         Ok(())
     }
 
+    /// Move a team to the top of the org, out of its parent team
+    pub(crate) fn detach_team(&self, org: &str, slug: &str) -> anyhow::Result<()> {
+        #[derive(serde::Serialize, Debug)]
+        struct Req {
+            parent_team_id: Option<u64>,
+        }
+        debug!("Moving team with slug '{slug}' in '{org}' out of its parent team");
+        if !self.dry_run {
+            self.client.send(
+                Method::PATCH,
+                &format!("orgs/{org}/teams/{slug}"),
+                &Req {
+                    parent_team_id: None,
+                },
+            )?;
+            self.record(|| Transaction::DetachTeam {
+                org: org.to_string(),
+                slug: slug.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
     /// Delete a team by name and org
     pub(crate) fn delete_team(&self, org: &str, slug: &str) -> anyhow::Result<()> {
         debug!("Deleting team with slug '{slug}' in '{org}'");
         if !self.dry_run {
             let method = Method::DELETE;
             let url = &format!("orgs/{org}/teams/{slug}");
-            let resp = self.client.req(method.clone(), url)?.send()?;
+            let resp = self.client.req_primary(method.clone(), url)?.send()?;
+            allow_not_found(resp, method, url)?;
+            self.record(|| Transaction::DeleteTeam {
+                org: org.to_string(),
+                slug: slug.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Remove a user from an org, along with all their teams and repo access in it
+    pub(crate) fn remove_org_member(&self, org: &str, login: &str) -> anyhow::Result<()> {
+        debug!("Removing user '{login}' from org '{org}'");
+        if !self.dry_run {
+            let method = Method::DELETE;
+            let url = &format!("orgs/{org}/memberships/{login}");
+            let resp = self.client.req_primary(method.clone(), url)?.send()?;
             allow_not_found(resp, method, url)?;
         }
         Ok(())
     }
 
+    /// Add a user to a team with a role, waiting for the configured delay afterwards
+    pub(crate) fn add_team_membership(
+        &self,
+        org: &str,
+        team: &str,
+        user: &str,
+        role: TeamRole,
+    ) -> anyhow::Result<()> {
+        self.set_team_membership(org, team, user, role)?;
+        if let Some(delay) = self.membership_add_delay {
+            if !self.dry_run {
+                std::thread::sleep(delay);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Set a user's membership in a team to a role
     pub(crate) fn set_team_membership(
         &self,
@@ -182,6 +317,12 @@ impl GitHubWrite {
                 &format!("orgs/{org}/teams/{team}/memberships/{user}"),
                 &Req { role },
             )?;
+            self.record(|| Transaction::SetMembership {
+                org: org.to_string(),
+                team: team.to_string(),
+                member: user.to_string(),
+                role,
+            })?;
         }
 
         Ok(())
@@ -198,8 +339,13 @@ impl GitHubWrite {
         if !self.dry_run {
             let url = &format!("orgs/{org}/teams/{team}/memberships/{user}");
             let method = Method::DELETE;
-            let resp = self.client.req(method.clone(), url)?.send()?;
+            let resp = self.client.req_primary(method.clone(), url)?.send()?;
             allow_not_found(resp, method, url)?;
+            self.record(|| Transaction::RemoveMembership {
+                org: org.to_string(),
+                team: team.to_string(),
+                member: user.to_string(),
+            })?;
         }
 
         Ok(())
@@ -238,6 +384,7 @@ impl GitHubWrite {
                 homepage: settings.homepage.clone(),
                 archived: false,
                 allow_auto_merge: Some(settings.auto_merge_enabled),
+                updated_at: None,
             })
         } else {
             Ok(self
@@ -282,7 +429,7 @@ impl GitHubWrite {
         debug!("Adding repository {repository_id} to installation {installation_id}");
         if !self.dry_run {
             self.client
-                .req(
+                .req_primary(
                     Method::PUT,
                     &format!("user/installations/{installation_id}/repositories/{repository_id}"),
                 )?
@@ -300,7 +447,7 @@ impl GitHubWrite {
         debug!("Removing repository {repository_id} from installation {installation_id}");
         if !self.dry_run {
             self.client
-                .req(
+                .req_primary(
                     Method::DELETE,
                     &format!("user/installations/{installation_id}/repositories/{repository_id}"),
                 )?
@@ -368,7 +515,7 @@ impl GitHubWrite {
         if !self.dry_run {
             let method = Method::DELETE;
             let url = &format!("orgs/{org}/teams/{team}/repos/{org}/{repo}");
-            let resp = self.client.req(method.clone(), url)?.send()?;
+            let resp = self.client.req_primary(method.clone(), url)?.send()?;
             allow_not_found(resp, method, url)?;
         }
 
@@ -386,7 +533,7 @@ impl GitHubWrite {
         if !self.dry_run {
             let method = Method::DELETE;
             let url = &format!("repos/{org}/{repo}/collaborators/{collaborator}");
-            let resp = self.client.req(method.clone(), url)?.send()?;
+            let resp = self.client.req_primary(method.clone(), url)?.send()?;
             allow_not_found(resp, method, url)?;
         }
         Ok(())
@@ -461,7 +608,7 @@ impl GitHubWrite {
         }
 
         if !self.dry_run {
-            let _: serde_json::Value = self.client.graphql(
+            let _: serde_json::Value = self.client.graphql_primary(
                 &query,
                 Params {
                     id,
@@ -503,7 +650,7 @@ impl GitHubWrite {
                     }
                 }
             ";
-            let _: serde_json::Value = self.client.graphql(query, Params { id })?;
+            let _: serde_json::Value = self.client.graphql_primary(query, Params { id })?;
         }
         Ok(())
     }
@@ -0,0 +1,54 @@
+use anyhow::Context;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An append-only file recording the membership changes applied to GitHub, one JSON object per
+/// line, so that it's possible to find out later why and when a change was made.
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    /// The commit of the team repo the changes come from
+    source_ref: String,
+}
+
+/// A membership change, as recorded in the [`AuditLog`]
+#[derive(serde::Serialize)]
+pub(crate) struct AuditEntry<'a> {
+    pub(crate) operation: &'a str,
+    pub(crate) org: &'a str,
+    pub(crate) team: &'a str,
+    pub(crate) member: &'a str,
+    pub(crate) reason: &'a str,
+}
+
+impl AuditLog {
+    pub(crate) fn new(path: PathBuf, source_ref: String) -> Self {
+        Self { path, source_ref }
+    }
+
+    pub(crate) fn record(&self, entry: &AuditEntry<'_>) -> anyhow::Result<()> {
+        #[derive(serde::Serialize)]
+        struct Line<'a> {
+            /// Seconds since the Unix epoch
+            applied_at: u64,
+            #[serde(flatten)]
+            entry: &'a AuditEntry<'a>,
+            source_ref: &'a str,
+        }
+
+        let mut line = serde_json::to_string(&Line {
+            applied_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            entry,
+            source_ref: &self.source_ref,
+        })?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open the audit log at {}", self.path.display()))?;
+        // One write per line, so that the entries of concurrently applied diffs don't interleave
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("failed to write the audit log at {}", self.path.display()))
+    }
+}
@@ -1,32 +1,153 @@
 mod api;
+mod audit;
 #[cfg(test)]
 mod tests;
+mod transactions;
+mod usernames;
 
 use self::api::{BranchProtectionOp, TeamPrivacy, TeamRole};
-use crate::github::api::{GithubRead, Login, PushAllowanceActor, RepoPermission, RepoSettings};
-use log::debug;
+use crate::github::api::{Login, PushAllowanceActor, RepoPermission, RepoSettings};
+use crate::utils::{days_since_epoch, for_each_concurrently};
+use anyhow::Context;
+use log::{debug, error, info, warn};
 use rust_team_data::v1::{Bot, BranchProtectionMode, MergeBot};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter, Write};
+use std::path::Path;
+use std::sync::Mutex;
 
-pub(crate) use self::api::{GitHubApiRead, GitHubWrite, HttpClient};
+pub(crate) use self::api::{GitHubApiRead, GitHubWrite, GithubRead, HttpClient};
+pub(crate) use self::audit::AuditLog;
+pub(crate) use self::transactions::{read_transactions, replay, TransactionLog};
 
 static DEFAULT_DESCRIPTION: &str = "Managed by the rust-lang/team repository.";
 static DEFAULT_PRIVACY: TeamPrivacy = TeamPrivacy::Closed;
+/// The community health files every public repo should have, either its own or the defaults from
+/// the `.github` repo of its org
+static KEY_HEALTH_FILES: &[&str] = &["code_of_conduct", "issue_template", "pull_request_template"];
+
+/// How the GitHub diff is computed, and what is reported along with it
+#[derive(Default)]
+pub(crate) struct DiffOptions {
+    /// Remove the managed teams from the repos that aren't in the team repo
+    pub(crate) remove_unmanaged_repo_grants: bool,
+    /// Report the org members without a verified email in one of these domains
+    pub(crate) allowed_email_domains: Vec<String>,
+    /// Report the repos missing community health files or a security policy
+    pub(crate) check_health_files: bool,
+    /// Report the direct collaborators of repos that already get access through the repo's teams
+    pub(crate) check_collaborator_grants: bool,
+    /// Leave alone the members of the teams synchronized from IdP groups, which costs a request
+    /// per team
+    pub(crate) check_idp_groups: bool,
+    /// The accounts removed from the managed orgs and all their teams, whatever the team repo says
+    pub(crate) denied_users: HashSet<String>,
+    pub(crate) lead_maintainers: LeadMaintainers,
+    /// How many repos are read from GitHub at the same time to compute their diff
+    pub(crate) read_concurrency: usize,
+}
+
+/// The orgs whose team leads are made maintainers of the GitHub teams of their team, like org
+/// owners are, without having to designate them team by team.
+#[derive(Default)]
+pub(crate) struct LeadMaintainers {
+    orgs: HashSet<String>,
+    /// GitHub teams (as `org/name`) of those orgs where leads are regular members
+    excluded_teams: HashSet<String>,
+}
+
+impl LeadMaintainers {
+    /// Parses a comma-separated list of orgs, and of `!org/name` GitHub teams to exclude
+    pub(crate) fn parse(spec: &str) -> Self {
+        let mut lead_maintainers = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.strip_prefix('!') {
+                Some(team) => lead_maintainers.excluded_teams.insert(team.to_string()),
+                None => lead_maintainers.orgs.insert(entry.to_string()),
+            };
+        }
+        lead_maintainers
+    }
+
+    fn applies_to(&self, org: &str, team: &str) -> bool {
+        self.orgs.contains(org) && !self.excluded_teams.contains(&format!("{org}/{team}"))
+    }
+}
 
 pub(crate) fn create_diff(
     github: Box<dyn GithubRead>,
     teams: Vec<rust_team_data::v1::Team>,
     repos: Vec<rust_team_data::v1::Repo>,
+    username_cache: Option<&Path>,
+    options: DiffOptions,
 ) -> anyhow::Result<Diff> {
-    let github = SyncGitHub::new(github, teams, repos)?;
-    github.diff_all()
+    let DiffOptions {
+        remove_unmanaged_repo_grants,
+        allowed_email_domains,
+        check_health_files,
+        check_collaborator_grants,
+        check_idp_groups,
+        denied_users,
+        lead_maintainers,
+        read_concurrency,
+    } = options;
+    let mut github = SyncGitHub::new(github, teams, repos, username_cache)?;
+    github.lead_maintainers = lead_maintainers;
+    github.read_concurrency = read_concurrency;
+    github.check_idp_groups = check_idp_groups;
+    for (org, domain) in github.pending_domain_verifications() {
+        warn!("domain {domain} of {org} is still pending verification");
+    }
+    if !allowed_email_domains.is_empty() {
+        for (org, login) in github.email_domain_violations(&allowed_email_domains)? {
+            warn!("member '{login}' of {org} has no verified email in the allowed domains");
+        }
+    }
+    if check_collaborator_grants {
+        for (org, repo, login, direct, through_teams) in github.excess_collaborator_grants() {
+            if direct.rank() > through_teams.rank() {
+                warn!(
+                    "collaborator '{login}' has {direct} on {org}/{repo}, more than the \
+                     {through_teams} their teams give them"
+                );
+            } else {
+                warn!(
+                    "collaborator '{login}' has {direct} on {org}/{repo}, which their teams \
+                     already give them ({through_teams})"
+                );
+            }
+        }
+    }
+    let mut diff = github.diff_all()?;
+    if !denied_users.is_empty() {
+        diff.remove_denied_users(&denied_users);
+        diff.org_member_removals = github.denied_org_members(&denied_users)?;
+    }
+    if check_health_files {
+        for (org, repo, files) in github.missing_health_files(&diff.repo_diffs)? {
+            warn!(
+                "repo {org}/{repo} has no {}, neither its own nor from {org}/.github",
+                files.join(", ")
+            );
+        }
+    }
+    if remove_unmanaged_repo_grants {
+        diff.repo_diffs.extend(github.diff_unmanaged_repos()?);
+    }
+    Ok(diff)
 }
 
 type OrgName = String;
 type RepoName = String;
-
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// A GitHub team merged from the teams defining it, with their alumni, their leads and their names
+type MergedGitHubTeam = (
+    rust_team_data::v1::GitHubTeam,
+    HashSet<u64>,
+    HashSet<u64>,
+    Vec<String>,
+);
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize)]
 enum GithubApp {
     RenovateBot,
     /// New Rust implementation of Bors
@@ -61,12 +182,21 @@ struct OrgAppInstallation {
     repositories: HashSet<RepoName>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
 struct AppInstallation {
     app: GithubApp,
     installation_id: u64,
 }
 
+/// A user of the team repo whose id doesn't resolve on GitHub anymore, while their login now
+/// belongs to another id, most likely because their account was migrated or merged
+#[derive(Debug, Clone, serde::Serialize)]
+struct AccountMigration {
+    login: String,
+    data_id: u64,
+    current_id: u64,
+}
+
 struct SyncGitHub {
     github: Box<dyn GithubRead>,
     teams: Vec<rust_team_data::v1::Team>,
@@ -74,13 +204,25 @@ struct SyncGitHub {
     usernames_cache: HashMap<u64, String>,
     org_owners: HashMap<OrgName, HashSet<u64>>,
     org_apps: HashMap<OrgName, Vec<OrgAppInstallation>>,
+    /// The users invited to join each org, with when they were invited
+    org_invitations: HashMap<OrgName, HashMap<String, Option<String>>>,
+    lead_maintainers: LeadMaintainers,
+    read_concurrency: usize,
+    /// Whether the teams are checked for IdP groups their members are synchronized from
+    check_idp_groups: bool,
+    /// The ids of the team repo that were replaced by the current ids of their logins
+    account_migrations: Vec<AccountMigration>,
+    /// The users that are in the team repo in any way: members or alumni of any team, or members
+    /// of any GitHub team
+    data_users: HashSet<u64>,
 }
 
 impl SyncGitHub {
     pub(crate) fn new(
         github: Box<dyn GithubRead>,
-        teams: Vec<rust_team_data::v1::Team>,
+        mut teams: Vec<rust_team_data::v1::Team>,
         repos: Vec<rust_team_data::v1::Repo>,
+        username_cache: Option<&Path>,
     ) -> anyhow::Result<Self> {
         debug!("caching mapping between user ids and usernames");
         let users = teams
@@ -92,7 +234,23 @@ impl SyncGitHub {
             .collect::<HashSet<_>>()
             .into_iter()
             .collect::<Vec<_>>();
-        let usernames_cache = github.usernames(&users)?;
+        let mut usernames_cache = match username_cache {
+            Some(path) => usernames::resolve_cached(&*github, &users, path)?,
+            None => github.usernames(&users)?,
+        };
+        let account_migrations =
+            migrate_unresolved_users(&*github, &mut teams, &mut usernames_cache)?;
+        let data_users = teams
+            .iter()
+            .flat_map(|t| t.members.iter().chain(&t.alumni).map(|m| m.github_id))
+            .chain(
+                teams
+                    .iter()
+                    .filter_map(|t| t.github.as_ref())
+                    .flat_map(|gh| &gh.teams)
+                    .flat_map(|gh_team| gh_team.members.iter().copied()),
+            )
+            .collect();
 
         debug!("caching organization owners");
         let orgs = teams
@@ -104,10 +262,15 @@ impl SyncGitHub {
 
         let mut org_owners = HashMap::new();
         let mut org_apps = HashMap::new();
+        let mut org_invitations = HashMap::new();
 
         for org in &orgs {
             org_owners.insert((*org).to_string(), github.org_owners(org)?);
 
+            let invites = github.org_invitations(org)?;
+            report_pending_invitations(org, &invites);
+            org_invitations.insert(org.to_string(), invites);
+
             let mut installations: Vec<OrgAppInstallation> = vec![];
 
             for installation in github.org_app_installations(org)? {
@@ -135,6 +298,12 @@ impl SyncGitHub {
             usernames_cache,
             org_owners,
             org_apps,
+            org_invitations,
+            lead_maintainers: LeadMaintainers::default(),
+            read_concurrency: 1,
+            check_idp_groups: false,
+            account_migrations,
+            data_users,
         })
     }
 
@@ -143,40 +312,91 @@ impl SyncGitHub {
         let repo_diffs = self.diff_repos()?;
 
         Ok(Diff {
+            account_migrations: self.account_migrations.clone(),
             team_diffs,
             repo_diffs,
+            org_member_removals: Vec::new(),
         })
     }
 
     fn diff_teams(&self) -> anyhow::Result<Vec<TeamDiff>> {
-        let mut diffs = Vec::new();
-        let mut unseen_github_teams = HashMap::new();
+        // The same GitHub team can be claimed by several teams of the team repo. Their members are
+        // merged, so that the definitions don't undo each other's changes.
+        let mut github_teams: Vec<MergedGitHubTeam> = Vec::new();
         for team in &self.teams {
             if let Some(gh) = &team.github {
                 for github_team in &gh.teams {
-                    // Get existing teams we haven't seen yet
-                    let unseen_github_teams = match unseen_github_teams.get_mut(&github_team.org) {
-                        Some(ts) => ts,
-                        None => {
-                            let ts: HashMap<_, _> = self
-                                .github
-                                .org_teams(&github_team.org)?
-                                .into_iter()
-                                .collect();
-                            unseen_github_teams
-                                .entry(github_team.org.clone())
-                                .or_insert(ts)
+                    let alumni = team.alumni.iter().map(|m| m.github_id);
+                    let leads = team
+                        .members
+                        .iter()
+                        .filter(|m| m.is_lead)
+                        .map(|m| m.github_id);
+                    match github_teams
+                        .iter_mut()
+                        .find(|(t, ..)| t.org == github_team.org && t.name == github_team.name)
+                    {
+                        Some((merged, merged_alumni, merged_leads, defined_by)) => {
+                            for member in &github_team.members {
+                                if !merged.members.contains(member) {
+                                    merged.members.push(*member);
+                                }
+                            }
+                            merged_alumni.extend(alumni);
+                            merged_leads.extend(leads);
+                            defined_by.push(team.name.clone());
                         }
-                    };
-                    // Remove the current team from the collection of unseen GitHub teams
-                    unseen_github_teams.remove(&github_team.name);
-
-                    diffs.push(self.diff_team(github_team)?);
+                        None => github_teams.push((
+                            github_team.clone(),
+                            alumni.collect(),
+                            leads.collect(),
+                            vec![team.name.clone()],
+                        )),
+                    }
                 }
             }
         }
 
-        let delete_diffs = unseen_github_teams
+        let mut diffs = Vec::new();
+        let mut unseen_github_teams = HashMap::new();
+        // Org -> team -> (slug, parent team), for the teams nested in another one on GitHub
+        let mut team_parents: HashMap<String, HashMap<String, (String, String)>> = HashMap::new();
+        for (github_team, alumni, leads, defined_by) in github_teams {
+            // Get existing teams we haven't seen yet
+            let unseen_github_teams = match unseen_github_teams.get_mut(&github_team.org) {
+                Some(ts) => ts,
+                None => {
+                    let mut ts = HashMap::new();
+                    let parents = team_parents.entry(github_team.org.clone()).or_default();
+                    for (name, slug, parent) in self.github.org_teams(&github_team.org)? {
+                        if let Some(parent) = parent {
+                            parents.insert(name.clone(), (slug.clone(), parent));
+                        }
+                        ts.insert(name, slug);
+                    }
+                    unseen_github_teams
+                        .entry(github_team.org.clone())
+                        .or_insert(ts)
+                }
+            };
+            // Remove the current team from the collection of unseen GitHub teams
+            unseen_github_teams.remove(&github_team.name);
+
+            let duplicate_definitions = if defined_by.len() > 1 {
+                warn!(
+                    "GitHub team {}/{} is defined by several teams ({}), their members are merged",
+                    github_team.org,
+                    github_team.name,
+                    defined_by.join(", ")
+                );
+                defined_by
+            } else {
+                Vec::new()
+            };
+            diffs.push(self.diff_team(&github_team, &alumni, &leads, duplicate_definitions)?);
+        }
+
+        let mut deleted_teams = unseen_github_teams
             .into_iter()
             .filter(|(org, _)| matches!(org.as_str(), "rust-lang" | "rust-lang-nursery")) // Only delete unmanaged teams in `rust-lang` and `rust-lang-nursery` for now
             .flat_map(|(org, remaining_github_teams)| {
@@ -188,32 +408,71 @@ impl SyncGitHub {
             .filter(|(_, (remaining_github_team, _))| {
                 !BOTS_TEAMS.contains(&remaining_github_team.as_str())
             })
-            .map(|(org, (name, slug))| TeamDiff::Delete(DeleteTeamDiff { org, name, slug }));
+            .collect::<Vec<_>>();
+        // The org teams come in no particular order
+        deleted_teams.sort();
+
+        // The children of a deleted team that are kept are affected by its deletion too
+        let deleted_names = deleted_teams
+            .iter()
+            .map(|(org, (name, _))| (org.clone(), name.clone()))
+            .collect::<HashSet<_>>();
+        let delete_diffs = deleted_teams.into_iter().map(|(org, (name, slug))| {
+            let mut child_teams = team_parents
+                .get(&org)
+                .into_iter()
+                .flatten()
+                .filter(|(child, (_, parent))| {
+                    **parent == name && !deleted_names.contains(&(org.clone(), (*child).clone()))
+                })
+                .map(|(child, (child_slug, _))| (child.clone(), child_slug.clone()))
+                .collect::<Vec<_>>();
+            child_teams.sort();
+            TeamDiff::Delete(DeleteTeamDiff {
+                org,
+                name,
+                slug,
+                child_teams,
+            })
+        });
 
         diffs.extend(delete_diffs);
 
         Ok(diffs)
     }
 
-    fn diff_team(&self, github_team: &rust_team_data::v1::GitHubTeam) -> anyhow::Result<TeamDiff> {
+    /// Diffs a GitHub team. The `alumni` of the team it belongs to are used to tell members that
+    /// left the team apart from members that were added on GitHub without going through the
+    /// team repo, and its `leads` can be made maintainers. `duplicate_definitions` lists the teams
+    /// defining the GitHub team, when there's more than one.
+    fn diff_team(
+        &self,
+        github_team: &rust_team_data::v1::GitHubTeam,
+        alumni: &HashSet<u64>,
+        leads: &HashSet<u64>,
+        duplicate_definitions: Vec<String>,
+    ) -> anyhow::Result<TeamDiff> {
         // Ensure the team exists and is consistent
         let team = match self.github.team(&github_team.org, &github_team.name)? {
             Some(team) => team,
             None => {
-                let members = github_team
+                let mut members = github_team
                     .members
                     .iter()
                     .map(|member| {
-                        let expected_role = self.expected_role(&github_team.org, *member);
+                        let expected_role = self.expected_role(github_team, leads, *member);
                         (self.usernames_cache[member].clone(), expected_role)
                     })
-                    .collect();
+                    .collect::<Vec<_>>();
+                // Invite the members in a stable order, so that the logs of runs are comparable
+                members.sort_by(|(a, _), (b, _)| a.cmp(b));
                 return Ok(TeamDiff::Create(CreateTeamDiff {
                     org: github_team.org.clone(),
                     name: github_team.name.clone(),
                     description: DEFAULT_DESCRIPTION.to_owned(),
                     privacy: DEFAULT_PRIVACY,
                     members,
+                    duplicate_definitions,
                 }));
             }
         };
@@ -237,6 +496,32 @@ impl SyncGitHub {
             privacy_diff = Some((team.privacy, DEFAULT_PRIVACY))
         }
 
+        // Teams synchronized from IdP groups get their members from the IdP, e.g. through SCIM
+        let idp_groups = if self.check_idp_groups {
+            self.github.team_idp_groups(&github_team.org, &team.slug)?
+        } else {
+            Vec::new()
+        };
+        if !idp_groups.is_empty() {
+            info!(
+                "not synchronizing the members of GitHub team {}/{}: they're managed by the IdP groups {}",
+                github_team.org,
+                github_team.name,
+                idp_groups.join(", ")
+            );
+            return Ok(TeamDiff::Edit(EditTeamDiff {
+                org: github_team.org.clone(),
+                name: team.name,
+                updated_at: team.updated_at,
+                name_diff,
+                description_diff,
+                privacy_diff,
+                member_diffs: Vec::new(),
+                duplicate_definitions,
+                idp_groups,
+            }));
+        }
+
         let mut member_diffs = Vec::new();
 
         let mut current_members = self.github.team_memberships(&team)?;
@@ -246,7 +531,7 @@ impl SyncGitHub {
 
         // Ensure all expected members are in the team
         for member in &github_team.members {
-            let expected_role = self.expected_role(&github_team.org, *member);
+            let expected_role = self.expected_role(github_team, leads, *member);
             let username = &self.usernames_cache[member];
             if let Some(member) = current_members.remove(member) {
                 if member.role != expected_role {
@@ -258,8 +543,13 @@ impl SyncGitHub {
                     member_diffs.push((username.clone(), MemberDiff::Noop));
                 }
             } else {
-                // Check if the user has been invited already
-                if invites.contains(username) {
+                // Check if the user has been invited already, to the team or to the org. Users
+                // invited to the org are added to the team by the first run after they accept.
+                let invited_to_org = self
+                    .org_invitations
+                    .get(&github_team.org)
+                    .is_some_and(|invites| invites.contains_key(username));
+                if invites.contains_key(username) || invited_to_org {
                     member_diffs.push((username.clone(), MemberDiff::Noop));
                 } else {
                     member_diffs.push((username.clone(), MemberDiff::Create(expected_role)));
@@ -267,28 +557,65 @@ impl SyncGitHub {
             }
         }
 
+        report_pending_invitations(
+            &format!("{}/{}", github_team.org, github_team.name),
+            &invites,
+        );
+
         // The previous cycle removed expected members from current_members, so it only contains
         // members to delete now.
-        for member in current_members.values() {
-            member_diffs.push((member.username.clone(), MemberDiff::Delete));
+        // Only the members that aren't in the team repo at all were added out-of-band. The others
+        // left the team, even without being listed among its alumni, e.g. when the team they were
+        // included from was dropped.
+        for (id, member) in &current_members {
+            let diff = if alumni.contains(id) || self.data_users.contains(id) {
+                MemberDiff::Delete
+            } else {
+                MemberDiff::DeleteOutOfBand
+            };
+            member_diffs.push((member.username.clone(), diff));
+        }
+        // Apply the membership changes in a stable order, as the members on GitHub come in no
+        // particular order
+        member_diffs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if github_team.members.is_empty() && !current_members.is_empty() {
+            warn!(
+                "GitHub team {}/{} has no members in the team repo, but {} on GitHub",
+                github_team.org,
+                github_team.name,
+                current_members.len()
+            );
         }
 
         Ok(TeamDiff::Edit(EditTeamDiff {
             org: github_team.org.clone(),
             name: team.name,
+            updated_at: team.updated_at,
             name_diff,
             description_diff,
             privacy_diff,
             member_diffs,
+            duplicate_definitions,
+            idp_groups,
         }))
     }
 
+    /// Diffs up to [`SyncGitHub::read_concurrency`] repos at the same time, as each of them takes
+    /// several requests. The diffs are in the order of the repos regardless.
     fn diff_repos(&self) -> anyhow::Result<Vec<RepoDiff>> {
-        let mut diffs = Vec::new();
-        for repo in &self.repos {
-            diffs.push(self.diff_repo(repo)?);
-        }
-        Ok(diffs)
+        let diffs = Mutex::new(Vec::with_capacity(self.repos.len()));
+        for_each_concurrently(
+            self.repos.iter().enumerate().collect(),
+            self.read_concurrency,
+            |(index, repo)| {
+                let diff = self.diff_repo(repo)?;
+                diffs.lock().unwrap().push((index, diff));
+                Ok(())
+            },
+        )?;
+        let mut diffs = diffs.into_inner().unwrap();
+        diffs.sort_by_key(|(index, _)| *index);
+        Ok(diffs.into_iter().map(|(_, diff)| diff).collect())
     }
 
     fn diff_repo(&self, expected_repo: &rust_team_data::v1::Repo) -> anyhow::Result<RepoDiff> {
@@ -324,7 +651,7 @@ impl SyncGitHub {
             }
         };
 
-        let permission_diffs = self.diff_permissions(expected_repo)?;
+        let (permission_diffs, pending_invitations) = self.diff_permissions(expected_repo)?;
         let branch_protection_diffs = self.diff_branch_protections(&actual_repo, expected_repo)?;
         let old_settings = RepoSettings {
             description: actual_repo.description.clone(),
@@ -367,31 +694,300 @@ impl SyncGitHub {
             name: actual_repo.name,
             repo_node_id: actual_repo.node_id,
             repo_id: actual_repo.repo_id,
+            updated_at: actual_repo.updated_at,
             settings_diff: (old_settings, new_settings),
             permission_diffs,
+            pending_invitations,
             branch_protection_diffs,
             app_installation_diffs,
         }))
     }
 
+    /// The domains added to the managed orgs whose verification (through a DNS record) wasn't
+    /// completed, as `(org, domain)` pairs. This is only reported, nothing is changed on GitHub,
+    /// so the orgs whose domains can't be read (only owners can) are skipped with a warning.
+    fn pending_domain_verifications(&self) -> Vec<(String, String)> {
+        let mut pending = Vec::new();
+        for org in self.managed_orgs() {
+            let mut domains = match self.github.org_unverified_domains(org) {
+                Ok(domains) => domains,
+                Err(err) => {
+                    warn!("cannot check the domains of {org}: {err:#}");
+                    continue;
+                }
+            };
+            domains.sort();
+            pending.extend(domains.into_iter().map(|domain| (org.clone(), domain)));
+        }
+        pending
+    }
+
+    /// The members of the managed orgs that are on the `denied` list, to remove from the orgs
+    fn denied_org_members(
+        &self,
+        denied: &HashSet<String>,
+    ) -> anyhow::Result<Vec<OrgMemberRemoval>> {
+        let mut denied = denied.iter().collect::<Vec<_>>();
+        denied.sort();
+        let mut removals = Vec::new();
+        for org in self.managed_orgs() {
+            for login in &denied {
+                if self.github.org_membership(org, login)? {
+                    removals.push(OrgMemberRemoval {
+                        org: org.clone(),
+                        login: login.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(removals)
+    }
+
+    /// The orgs the GitHub teams of the team repo belong to
+    fn managed_orgs(&self) -> BTreeSet<&String> {
+        self.teams
+            .iter()
+            .filter_map(|t| t.github.as_ref())
+            .flat_map(|gh| &gh.teams)
+            .map(|gh_team| &gh_team.org)
+            .collect()
+    }
+
+    /// The members of the managed orgs that have no verified email in any of the `allowed`
+    /// domains, as `(org, login)` pairs. This is only reported, nothing is changed on GitHub.
+    fn email_domain_violations(&self, allowed: &[String]) -> anyhow::Result<Vec<(String, String)>> {
+        let mut violations = Vec::new();
+        for org in self.managed_orgs() {
+            let mut members = self
+                .github
+                .org_members_verified_emails(org)?
+                .into_iter()
+                .collect::<Vec<_>>();
+            members.sort();
+            for (login, emails) in members {
+                let compliant = emails.iter().any(|email| {
+                    email
+                        .rsplit_once('@')
+                        .is_some_and(|(_, domain)| allowed.iter().any(|a| a == domain))
+                });
+                if !compliant {
+                    violations.push((org.clone(), login));
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// The managed repos missing some of the [`KEY_HEALTH_FILES`] or a security policy, as
+    /// `(org, repo, missing files)`. This is only reported, nothing is changed on GitHub.
+    ///
+    /// The `repo_diffs` of the managed repos tell which of them don't exist yet.
+    fn missing_health_files(
+        &self,
+        repo_diffs: &[RepoDiff],
+    ) -> anyhow::Result<Vec<(String, String, Vec<&'static str>)>> {
+        let created: HashSet<(&str, &str)> = repo_diffs
+            .iter()
+            .filter_map(|diff| match diff {
+                RepoDiff::Create(c) => Some((c.org.as_str(), c.name.as_str())),
+                RepoDiff::Update(_) => None,
+            })
+            .collect();
+        let mut org_defaults: HashMap<&str, HashSet<String>> = HashMap::new();
+        let mut missing = Vec::new();
+        // Archived repos can't get new files anyway
+        for repo in self.repos.iter().filter(|r| !r.archived) {
+            // Repos that don't exist yet are created without any file
+            if created.contains(&(repo.org.as_str(), repo.name.as_str())) {
+                continue;
+            }
+            let mut lacking = Vec::new();
+            // Private repos have no community profile
+            if !repo.private {
+                if !org_defaults.contains_key(repo.org.as_str()) {
+                    let defaults = self.github.repo_health_files(&repo.org, ".github")?;
+                    org_defaults.insert(&repo.org, defaults.unwrap_or_default());
+                }
+                let files = self.github.repo_health_files(&repo.org, &repo.name)?;
+                let files = files.unwrap_or_default();
+                let defaults = &org_defaults[repo.org.as_str()];
+                lacking.extend(
+                    KEY_HEALTH_FILES
+                        .iter()
+                        .copied()
+                        .filter(|file| !files.contains(*file) && !defaults.contains(*file)),
+                );
+            }
+            // GitHub already falls back to the security policy of the `.github` repo of the org
+            if !self
+                .github
+                .repo_has_security_policy(&repo.org, &repo.name)?
+            {
+                lacking.push("security_policy");
+            }
+            if !lacking.is_empty() {
+                missing.push((repo.org.clone(), repo.name.clone(), lacking));
+            }
+        }
+        Ok(missing)
+    }
+
+    /// The direct collaborators of the managed repos that are also members of one of the teams of
+    /// the repo, as `(org, repo, login, direct permission, permission through the teams)`. Such a
+    /// grant is either redundant or escalates the access of the user beyond their teams. This is
+    /// only reported, nothing is changed on GitHub.
+    fn excess_collaborator_grants(
+        &self,
+    ) -> Vec<(String, String, String, RepoPermission, RepoPermission)> {
+        let mut team_members: HashMap<(&str, &str), HashSet<&str>> = HashMap::new();
+        for gh_team in self
+            .teams
+            .iter()
+            .filter_map(|t| t.github.as_ref())
+            .flat_map(|gh| &gh.teams)
+        {
+            team_members
+                .entry((&gh_team.org, &gh_team.name))
+                .or_default()
+                .extend(
+                    gh_team
+                        .members
+                        .iter()
+                        .filter_map(|id| self.usernames_cache.get(id))
+                        .map(String::as_str),
+                );
+        }
+
+        let mut grants = Vec::new();
+        for repo in &self.repos {
+            for member in &repo.members {
+                let through_teams = repo
+                    .teams
+                    .iter()
+                    .filter(|team| {
+                        team_members
+                            .get(&(repo.org.as_str(), team.name.as_str()))
+                            .is_some_and(|members| members.contains(member.name.as_str()))
+                    })
+                    .map(|team| convert_permission(&team.permission))
+                    .max_by_key(RepoPermission::rank);
+                if let Some(through_teams) = through_teams {
+                    grants.push((
+                        repo.org.clone(),
+                        repo.name.clone(),
+                        member.name.clone(),
+                        convert_permission(&member.permission),
+                        through_teams,
+                    ));
+                }
+            }
+        }
+        grants
+    }
+
+    /// Removes the access of the teams managed by the team repo from the repos of the managed orgs
+    /// that aren't in the team repo (anymore). The repos themselves are left alone.
+    fn diff_unmanaged_repos(&self) -> anyhow::Result<Vec<RepoDiff>> {
+        let mut managed_teams: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        for gh_team in self
+            .teams
+            .iter()
+            .filter_map(|t| t.github.as_ref())
+            .flat_map(|gh| &gh.teams)
+        {
+            managed_teams
+                .entry(&gh_team.org)
+                .or_default()
+                .insert(&gh_team.name);
+        }
+
+        let mut diffs = Vec::new();
+        for (org, teams) in managed_teams {
+            let managed_repos = self
+                .repos
+                .iter()
+                .filter(|r| r.org == org)
+                .map(|r| r.name.as_str())
+                .collect::<HashSet<_>>();
+            for repo in self.github.org_repos(org)? {
+                if managed_repos.contains(repo.name.as_str()) {
+                    continue;
+                }
+                let permission_diffs = self
+                    .github
+                    .repo_teams(org, &repo.name)?
+                    .into_iter()
+                    .filter(|t| teams.contains(t.name.as_str()))
+                    .map(|t| RepoPermissionAssignmentDiff {
+                        collaborator: RepoCollaborator::Team(t.name),
+                        diff: RepoPermissionDiff::Delete(t.permission),
+                    })
+                    .collect::<Vec<_>>();
+                if permission_diffs.is_empty() {
+                    continue;
+                }
+                let settings = RepoSettings {
+                    description: repo.description.clone(),
+                    homepage: repo.homepage.clone(),
+                    archived: repo.archived,
+                    auto_merge_enabled: repo.allow_auto_merge.unwrap_or(false),
+                };
+                diffs.push(RepoDiff::Update(UpdateRepoDiff {
+                    org: org.to_string(),
+                    name: repo.name,
+                    repo_node_id: repo.node_id,
+                    repo_id: repo.repo_id,
+                    updated_at: repo.updated_at,
+                    settings_diff: (settings.clone(), settings),
+                    permission_diffs,
+                    pending_invitations: Vec::new(),
+                    branch_protection_diffs: Vec::new(),
+                    app_installation_diffs: Vec::new(),
+                }));
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Diffs the permissions of a repo, also returning the users whose pending invitation to
+    /// collaborate on it already matches the team repo
     fn diff_permissions(
         &self,
         expected_repo: &rust_team_data::v1::Repo,
-    ) -> anyhow::Result<Vec<RepoPermissionAssignmentDiff>> {
+    ) -> anyhow::Result<(Vec<RepoPermissionAssignmentDiff>, Vec<String>)> {
         let actual_teams: HashMap<_, _> = self
             .github
             .repo_teams(&expected_repo.org, &expected_repo.name)?
             .into_iter()
             .map(|t| (t.name.clone(), t))
             .collect();
-        let actual_collaborators: HashMap<_, _> = self
+        let mut actual_collaborators: HashMap<_, _> = self
             .github
             .repo_collaborators(&expected_repo.org, &expected_repo.name)?
             .into_iter()
             .map(|u| (u.name.clone(), u))
             .collect();
+        // Invited users are treated as collaborators with the permission they were invited with,
+        // so that they aren't invited again on every run until they accept
+        let mut pending_invitations = Vec::new();
+        for invitee in self
+            .github
+            .repo_invitations(&expected_repo.org, &expected_repo.name)?
+        {
+            if !actual_collaborators.contains_key(&invitee.name) {
+                pending_invitations.push(invitee.name.clone());
+                actual_collaborators.insert(invitee.name.clone(), invitee);
+            }
+        }
 
-        calculate_permission_diffs(expected_repo, actual_teams, actual_collaborators)
+        let diffs = calculate_permission_diffs(expected_repo, actual_teams, actual_collaborators)?;
+        pending_invitations.retain(|name| {
+            !diffs
+                .iter()
+                .any(|d| matches!(&d.collaborator, RepoCollaborator::User(user) if user == name))
+        });
+        pending_invitations.sort();
+        Ok((diffs, pending_invitations))
     }
 
     fn diff_branch_protections(
@@ -424,6 +1020,7 @@ impl SyncGitHub {
             branch_protection_diffs.push(BranchProtectionDiff {
                 pattern: branch_protection.pattern.clone(),
                 operation,
+                held_review_count_reduction: None,
             })
         }
 
@@ -433,6 +1030,7 @@ impl SyncGitHub {
             BranchProtectionDiff {
                 pattern: name,
                 operation: BranchProtectionDiffOperation::Delete(id),
+                held_review_count_reduction: None,
             }
         }));
 
@@ -486,12 +1084,21 @@ impl SyncGitHub {
         Ok(diff)
     }
 
-    fn expected_role(&self, org: &str, user: u64) -> TeamRole {
-        if let Some(true) = self
+    fn expected_role(
+        &self,
+        github_team: &rust_team_data::v1::GitHubTeam,
+        leads: &HashSet<u64>,
+        user: u64,
+    ) -> TeamRole {
+        let is_owner = self
             .org_owners
-            .get(org)
-            .map(|owners| owners.contains(&user))
-        {
+            .get(&github_team.org)
+            .is_some_and(|owners| owners.contains(&user));
+        let is_maintaining_lead = leads.contains(&user)
+            && self
+                .lead_maintainers
+                .applies_to(&github_team.org, &github_team.name);
+        if is_owner || is_maintaining_lead {
             TeamRole::Maintainer
         } else {
             TeamRole::Member
@@ -583,6 +1190,114 @@ fn calculate_permission_diffs(
     Ok(permissions)
 }
 
+/// Handles the users of the GitHub teams whose id in the team repo doesn't resolve to a GitHub user
+/// anymore. When their login now belongs to another id, their account was most likely migrated:
+/// the id is replaced by the current one in the `teams`, so that their memberships are kept.
+fn migrate_unresolved_users(
+    github: &dyn GithubRead,
+    teams: &mut [rust_team_data::v1::Team],
+    usernames: &mut HashMap<u64, String>,
+) -> anyhow::Result<Vec<AccountMigration>> {
+    let logins = teams
+        .iter()
+        .flat_map(|t| t.members.iter().chain(&t.alumni))
+        .map(|m| (m.github_id, m.github.clone()))
+        .collect::<HashMap<_, _>>();
+    let unresolved = teams
+        .iter()
+        .filter_map(|t| t.github.as_ref())
+        .flat_map(|gh| &gh.teams)
+        .flat_map(|gh_team| &gh_team.members)
+        .filter(|id| !usernames.contains_key(id))
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    let mut migrations = Vec::new();
+    for data_id in unresolved {
+        let Some(login) = logins.get(&data_id) else {
+            anyhow::bail!("user id {data_id} of the team repo doesn't resolve to a GitHub user");
+        };
+        match github.user_id(login)? {
+            Some(current_id) if current_id != data_id => {
+                warn!(
+                    "user id {data_id} of '{login}' doesn't resolve anymore, but '{login}' is now user id {current_id}: the account was likely migrated"
+                );
+                usernames.insert(current_id, login.clone());
+                migrations.push(AccountMigration {
+                    login: login.clone(),
+                    data_id,
+                    current_id,
+                });
+            }
+            _ => anyhow::bail!(
+                "user id {data_id} of '{login}' doesn't resolve to a GitHub user anymore"
+            ),
+        }
+    }
+
+    let current_id = |id: u64| {
+        migrations
+            .iter()
+            .find(|m| m.data_id == id)
+            .map_or(id, |m| m.current_id)
+    };
+    for team in teams.iter_mut() {
+        for member in team.members.iter_mut().chain(team.alumni.iter_mut()) {
+            member.github_id = current_id(member.github_id);
+        }
+        for gh_team in team.github.iter_mut().flat_map(|gh| &mut gh.teams) {
+            for member in &mut gh_team.members {
+                *member = current_id(*member);
+            }
+        }
+    }
+    Ok(migrations)
+}
+
+/// Logs the invitations to a team or an org that weren't accepted yet, flagging the ones pending
+/// for more than [`STALE_INVITATION_DAYS`] so that they can be followed up on or cancelled.
+fn report_pending_invitations(target: &str, invites: &HashMap<String, Option<String>>) {
+    if invites.is_empty() {
+        return;
+    }
+    info!("{target} has {} pending invitation(s)", invites.len());
+    let today = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| (d.as_secs() / 86400) as i64)
+        .unwrap_or_default();
+    let mut invites = invites.iter().collect::<Vec<_>>();
+    invites.sort();
+    for (login, created_at) in invites {
+        match created_at.as_deref().and_then(epoch_days) {
+            Some(day) if today - day > STALE_INVITATION_DAYS => warn!(
+                "the invitation of '{login}' to {target} is pending for {} days",
+                today - day
+            ),
+            Some(day) => info!(
+                "the invitation of '{login}' to {target} is pending for {} days",
+                today - day
+            ),
+            None => info!("the invitation of '{login}' to {target} is pending"),
+        }
+    }
+}
+
+/// The number of days between the Unix epoch and the date of an ISO 8601 timestamp
+fn epoch_days(timestamp: &str) -> Option<i64> {
+    let mut parts = timestamp
+        .get(..10)?
+        .split('-')
+        .map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(days_since_epoch(year, month, day))
+}
+
+/// Whether a resource changed on GitHub between computing the plan and applying it, in which case
+/// applying the planned edit would clobber the concurrent change.
+fn modified_since_plan(planned: &Option<String>, current: &Option<String>) -> bool {
+    planned.is_some() && planned != current
+}
+
 /// Returns `None` if the bot is not an actual bot user, but rather a GitHub app.
 fn bot_user_name(bot: &Bot) -> Option<&str> {
     match bot {
@@ -663,10 +1378,36 @@ pub fn construct_branch_protection(
 /// The special bot teams
 const BOTS_TEAMS: &[&str] = &["bors", "highfive", "rfcbot", "bots"];
 
+/// Team invitations pending for more than this many days are reported as stale
+const STALE_INVITATION_DAYS: i64 = 30;
+
 /// A diff between the team repo and the state on GitHub
+#[derive(serde::Serialize)]
 pub(crate) struct Diff {
+    account_migrations: Vec<AccountMigration>,
+    /// The teams and repos left unchanged aren't serialized, so that a saved plan only stops
+    /// matching when the changes do
+    #[serde(serialize_with = "serialize_team_changes")]
     team_diffs: Vec<TeamDiff>,
+    #[serde(serialize_with = "serialize_repo_changes")]
     repo_diffs: Vec<RepoDiff>,
+    /// Removals of users on the deny list from the orgs, applied after the teams and repos
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    org_member_removals: Vec<OrgMemberRemoval>,
+}
+
+fn serialize_team_changes<S: serde::Serializer>(
+    diffs: &[TeamDiff],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(diffs.iter().filter(|d| !d.noop()))
+}
+
+fn serialize_repo_changes<S: serde::Serializer>(
+    diffs: &[RepoDiff],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(diffs.iter().filter(|d| !d.noop()))
 }
 
 impl Diff {
@@ -678,13 +1419,346 @@ impl Diff {
         for repo_diff in self.repo_diffs {
             repo_diff.apply(sync)?;
         }
+        for removal in self.org_member_removals {
+            removal.apply(sync)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the diff to GitHub like [`Diff::apply`], but with up to `limit` teams, then up to
+    /// `limit` repos, applied at the same time. The changes of a single team or repo are still
+    /// applied in order, and the repos only once all the teams are done, as their permissions can
+    /// refer to teams created by the diff.
+    pub(crate) fn apply_concurrently(self, sync: &GitHubWrite, limit: usize) -> anyhow::Result<()> {
+        for_each_concurrently(self.team_diffs, limit, |diff| diff.apply(sync))?;
+        for_each_concurrently(self.repo_diffs, limit, |diff| diff.apply(sync))?;
+        for removal in self.org_member_removals {
+            removal.apply(sync)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the diff to GitHub like [`Diff::apply`], but keep going with the other teams when
+    /// one of them fails, and report for each user which orgs their membership changes were
+    /// applied in and which were left incomplete.
+    pub(crate) fn apply_reporting_users(self, sync: &GitHubWrite) -> anyhow::Result<()> {
+        // User -> org -> whether all of the user's membership changes in the org were applied
+        let mut users: BTreeMap<String, BTreeMap<String, bool>> = BTreeMap::new();
+        let mut failed_teams = 0;
+        for team_diff in self.team_diffs {
+            let org = team_diff.org().to_owned();
+            let members = team_diff.changed_members();
+            let applied = match team_diff.apply(sync) {
+                Ok(()) => true,
+                Err(err) => {
+                    // The error already names the team
+                    error!("{err:?}");
+                    failed_teams += 1;
+                    false
+                }
+            };
+            for member in members {
+                *users
+                    .entry(member)
+                    .or_default()
+                    .entry(org.clone())
+                    .or_insert(true) &= applied;
+            }
+        }
+
+        for (user, orgs) in &users {
+            let (applied, incomplete): (Vec<_>, Vec<_>) = orgs.iter().partition(|(_, ok)| **ok);
+            let applied = applied
+                .iter()
+                .map(|(org, _)| org.as_str())
+                .collect::<Vec<_>>();
+            let incomplete = incomplete
+                .iter()
+                .map(|(org, _)| org.as_str())
+                .collect::<Vec<_>>();
+            if incomplete.is_empty() {
+                info!("user '{user}': applied in {}", applied.join(", "));
+            } else {
+                error!(
+                    "user '{user}': applied in [{}], incomplete in [{}]",
+                    applied.join(", "),
+                    incomplete.join(", ")
+                );
+            }
+        }
+        if failed_teams > 0 {
+            anyhow::bail!("failed to apply the changes to {failed_teams} teams");
+        }
+
+        for repo_diff in self.repo_diffs {
+            repo_diff.apply(sync)?;
+        }
+        for removal in self.org_member_removals {
+            removal.apply(sync)?;
+        }
 
         Ok(())
     }
+
+    /// Don't remove the team members that were added out-of-band, so that maintainers can decide
+    /// whether to add them to the team repo instead
+    pub(crate) fn hold_out_of_band_removals(&mut self) {
+        for team_diff in &mut self.team_diffs {
+            if let TeamDiff::Edit(e) = team_diff {
+                for (_, diff) in &mut e.member_diffs {
+                    if let MemberDiff::DeleteOutOfBand = diff {
+                        *diff = MemberDiff::HoldOutOfBand;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hold the member removals of the teams that would lose more than `max_percent` of their
+    /// members, as that's more likely caused by a mistake in the data than intended. Teams listed
+    /// (as `org/name`) in `confirmed` are exempt.
+    pub(crate) fn hold_mass_removals(&mut self, max_percent: usize, confirmed: &[String]) {
+        for team_diff in &mut self.team_diffs {
+            if let TeamDiff::Edit(e) = team_diff {
+                if confirmed.contains(&format!("{}/{}", e.org, e.name))
+                    || e.removal_percent() <= max_percent
+                {
+                    continue;
+                }
+                for (_, diff) in &mut e.member_diffs {
+                    if let MemberDiff::Delete | MemberDiff::DeleteOutOfBand = diff {
+                        *diff = MemberDiff::HeldRemoval;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hold the reductions of the required approving review count of branch protections, as they
+    /// weaken the protections. Repos listed (as `org/name`) in `confirmed` are exempt.
+    pub(crate) fn hold_review_count_reductions(&mut self, confirmed: &[String]) {
+        for repo_diff in &mut self.repo_diffs {
+            if let RepoDiff::Update(u) = repo_diff {
+                if confirmed.contains(&format!("{}/{}", u.org, u.name)) {
+                    continue;
+                }
+                for diff in &mut u.branch_protection_diffs {
+                    diff.hold_review_count_reduction();
+                }
+            }
+        }
+    }
+
+    /// Never remove or demote the accounts the sync runs as, whatever the team repo or the other
+    /// switches say, as that would lock the sync out of the teams it manages.
+    pub(crate) fn keep_token_owners(&mut self, owners: &[String]) {
+        for team_diff in &mut self.team_diffs {
+            if let TeamDiff::Edit(e) = team_diff {
+                for (member, diff) in &mut e.member_diffs {
+                    if !owners.contains(member) {
+                        continue;
+                    }
+                    if let MemberDiff::Delete
+                    | MemberDiff::DeleteOutOfBand
+                    | MemberDiff::DeleteDenied
+                    | MemberDiff::ChangeRole((TeamRole::Maintainer, TeamRole::Member)) = diff
+                    {
+                        warn!(
+                            "not removing or demoting '{member}' in {}/{}: the sync runs as them",
+                            e.org, e.name
+                        );
+                        *diff = MemberDiff::KeepTokenOwner;
+                    }
+                }
+            }
+        }
+        self.org_member_removals.retain(|removal| {
+            let keep = !owners.contains(&removal.login);
+            if !keep {
+                warn!(
+                    "not removing '{}' from org '{}': the sync runs as them",
+                    removal.login, removal.org
+                );
+            }
+            keep
+        });
+    }
+
+    /// Remove the `denied` accounts (e.g. because they were compromised) from every team, and
+    /// don't add them back even if the team repo still lists them.
+    pub(crate) fn remove_denied_users(&mut self, denied: &HashSet<String>) {
+        for team_diff in &mut self.team_diffs {
+            match team_diff {
+                TeamDiff::Create(c) => c.members.retain(|(member, _)| {
+                    let is_denied = denied.contains(member);
+                    if is_denied {
+                        warn!(
+                            "the team repo still lists '{member}' in {}/{}, who is on the deny list",
+                            c.org, c.name
+                        );
+                    }
+                    !is_denied
+                }),
+                TeamDiff::Edit(e) => {
+                    for (member, diff) in &mut e.member_diffs {
+                        if !denied.contains(member) {
+                            continue;
+                        }
+                        if let MemberDiff::Create(_) | MemberDiff::ChangeRole(_) | MemberDiff::Noop =
+                            diff
+                        {
+                            warn!(
+                                "the team repo still lists '{member}' in {}/{}, who is on the deny list",
+                                e.org, e.name
+                            );
+                        }
+                        *diff = match diff {
+                            MemberDiff::Create(_) => MemberDiff::SkipDenied,
+                            _ => MemberDiff::DeleteDenied,
+                        };
+                    }
+                }
+                TeamDiff::Delete(_) => {}
+            }
+        }
+    }
+
+    /// Asks `confirm` whether to apply the changes of each team and repo, described as in the
+    /// plan, and drops the rejected ones. Returns the descriptions of the dropped changes.
+    pub(crate) fn confirm_each(&mut self, mut confirm: impl FnMut(&str) -> bool) -> Vec<String> {
+        let mut skipped = Vec::new();
+        let mut ask = |change: String| {
+            if change.is_empty() || confirm(&change) {
+                true
+            } else {
+                skipped.push(change.trim_end().to_string());
+                false
+            }
+        };
+        self.team_diffs.retain(|diff| ask(diff.to_string()));
+        self.repo_diffs.retain(|diff| ask(diff.to_string()));
+        self.org_member_removals
+            .retain(|removal| ask(removal.to_string()));
+        skipped
+    }
+
+    /// An estimate of the write requests applying the diff makes to GitHub, by kind of request.
+    /// Reads done while applying (e.g. to check that nothing changed since the plan) aren't
+    /// counted.
+    pub(crate) fn estimated_writes(&self) -> BTreeMap<&'static str, usize> {
+        let mut writes = BTreeMap::new();
+        let mut count = |kind, n: usize| {
+            if n > 0 {
+                *writes.entry(kind).or_insert(0) += n;
+            }
+        };
+        for team_diff in &self.team_diffs {
+            match team_diff {
+                TeamDiff::Create(_) => count("teams", 1),
+                TeamDiff::Delete(d) => count("teams", 1 + d.child_teams.len()),
+                TeamDiff::Edit(e) => {
+                    let edited = e.name_diff.is_some()
+                        || e.description_diff.is_some()
+                        || e.privacy_diff.is_some();
+                    count("teams", usize::from(edited));
+                }
+            }
+            count("team memberships", team_diff.changed_members().len());
+        }
+        for repo_diff in &self.repo_diffs {
+            match repo_diff {
+                RepoDiff::Create(c) => {
+                    count("repos", 1);
+                    count("repo permissions", c.permissions.len());
+                    count("branch protections", c.branch_protections.len());
+                    count("app installations", c.app_installations.len());
+                }
+                RepoDiff::Update(u) if !u.noop() => {
+                    count("repos", usize::from(u.settings_diff.0 != u.settings_diff.1));
+                    count("repo permissions", u.permission_diffs.len());
+                    // Reductions of the required approvals are counted apart, as they weaken
+                    // the protections
+                    let reductions = u
+                        .branch_protection_diffs
+                        .iter()
+                        .filter(|d| d.review_count_reduction().is_some())
+                        .count();
+                    count("required approval reductions", reductions);
+                    count(
+                        "branch protections",
+                        u.branch_protection_diffs.len() - reductions,
+                    );
+                    count("app installations", u.app_installation_diffs.len());
+                }
+                RepoDiff::Update(_) => {}
+            }
+        }
+        count("org memberships", self.org_member_removals.len());
+        writes
+    }
+
+    /// Entities that exist on GitHub but never came from the team repo, and that the diff would
+    /// remove. Removals asked for by the team repo, like offboarded members or teams taken off a
+    /// repo, aren't drift and are left out.
+    pub(crate) fn unmanaged_entities(&self) -> Vec<String> {
+        let mut unmanaged = Vec::new();
+        for team_diff in &self.team_diffs {
+            match team_diff {
+                TeamDiff::Delete(d) => unmanaged.push(format!("team '{}/{}'", d.org, d.name)),
+                TeamDiff::Edit(e) => {
+                    for (member, diff) in &e.member_diffs {
+                        if let MemberDiff::DeleteOutOfBand = diff {
+                            unmanaged
+                                .push(format!("member '{member}' of team '{}/{}'", e.org, e.name));
+                        }
+                    }
+                }
+                TeamDiff::Create(_) => {}
+            }
+        }
+        for repo_diff in &self.repo_diffs {
+            let RepoDiff::Update(u) = repo_diff else {
+                continue;
+            };
+            if !u.can_be_modified() {
+                continue;
+            }
+            for permission in &u.permission_diffs {
+                if let (RepoPermissionDiff::Delete(_), RepoCollaborator::User(name)) =
+                    (&permission.diff, &permission.collaborator)
+                {
+                    unmanaged.push(format!(
+                        "collaborator '{name}' on repo '{}/{}'",
+                        u.org, u.name
+                    ));
+                }
+            }
+        }
+        unmanaged
+    }
+
+    /// Display the diff grouped by organization rather than by category
+    pub(crate) fn by_org(&self) -> DiffByOrg<'_> {
+        DiffByOrg(self)
+    }
+}
+
+impl std::fmt::Display for AccountMigration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "⚠️ Suspected account migration: '{}' is user id {} in the team repo, but {} on GitHub",
+            self.login, self.data_id, self.current_id
+        )
+    }
 }
 
 impl std::fmt::Display for Diff {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for migration in &self.account_migrations {
+            write!(f, "{migration}")?;
+        }
         writeln!(f, "💻 Team Diffs:")?;
         for team_diff in &self.team_diffs {
             write!(f, "{team_diff}")?;
@@ -693,11 +1767,84 @@ impl std::fmt::Display for Diff {
         for repo_diff in &self.repo_diffs {
             write!(f, "{repo_diff}")?;
         }
+        if !self.org_member_removals.is_empty() {
+            writeln!(f, "💻 Org Member Diffs:")?;
+            for removal in &self.org_member_removals {
+                write!(f, "{removal}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A view of a [`Diff`] that shows the changes of each organization together
+pub(crate) struct DiffByOrg<'a>(&'a Diff);
+
+impl std::fmt::Display for DiffByOrg<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let orgs = self
+            .0
+            .team_diffs
+            .iter()
+            .map(|d| d.org())
+            .chain(self.0.repo_diffs.iter().map(|d| d.org()))
+            .chain(self.0.org_member_removals.iter().map(|r| r.org.as_str()))
+            .collect::<BTreeSet<_>>();
+        for migration in &self.0.account_migrations {
+            write!(f, "{migration}")?;
+        }
+        for org in orgs {
+            writeln!(f, "🏢 Org '{org}':")?;
+            writeln!(f, "💻 Team Diffs:")?;
+            for team_diff in self.0.team_diffs.iter().filter(|d| d.org() == org) {
+                write!(f, "{team_diff}")?;
+            }
+            writeln!(f, "💻 Repo Diffs:")?;
+            for repo_diff in self.0.repo_diffs.iter().filter(|d| d.org() == org) {
+                write!(f, "{repo_diff}")?;
+            }
+            let mut removals = self
+                .0
+                .org_member_removals
+                .iter()
+                .filter(|r| r.org == org)
+                .peekable();
+            if removals.peek().is_some() {
+                writeln!(f, "💻 Org Member Diffs:")?;
+                for removal in removals {
+                    write!(f, "{removal}")?;
+                }
+            }
+        }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// The removal of a user on the deny list from an org
+#[derive(Debug, serde::Serialize)]
+struct OrgMemberRemoval {
+    org: String,
+    login: String,
+}
+
+impl OrgMemberRemoval {
+    fn apply(&self, sync: &GitHubWrite) -> anyhow::Result<()> {
+        sync.remove_org_member(&self.org, &self.login)
+            .with_context(|| format!("failed to remove '{}' from org '{}'", self.login, self.org))
+    }
+}
+
+impl std::fmt::Display for OrgMemberRemoval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "❌ Removing '{}' from org '{}' (on the deny list)",
+            self.login, self.org
+        )
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
 enum RepoDiff {
     Create(CreateRepoDiff),
     Update(UpdateRepoDiff),
@@ -709,6 +1856,34 @@ impl RepoDiff {
             RepoDiff::Create(c) => c.apply(sync),
             RepoDiff::Update(u) => u.apply(sync),
         }
+        .with_context(|| {
+            format!(
+                "failed to apply the changes to repo '{}/{}'",
+                self.org(),
+                self.name()
+            )
+        })
+    }
+
+    fn org(&self) -> &str {
+        match self {
+            RepoDiff::Create(c) => &c.org,
+            RepoDiff::Update(u) => &u.org,
+        }
+    }
+
+    fn noop(&self) -> bool {
+        match self {
+            RepoDiff::Create(_) => false,
+            RepoDiff::Update(u) => u.noop(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            RepoDiff::Create(c) => &c.name,
+            RepoDiff::Update(u) => &u.name,
+        }
     }
 }
 
@@ -721,7 +1896,7 @@ impl std::fmt::Display for RepoDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct CreateRepoDiff {
     org: String,
     name: String,
@@ -743,6 +1918,7 @@ impl CreateRepoDiff {
             BranchProtectionDiff {
                 pattern: branch.clone(),
                 operation: BranchProtectionDiffOperation::Create(protection.clone()),
+                held_review_count_reduction: None,
             }
             .apply(sync, &self.org, &self.name, &repo.node_id)?;
         }
@@ -787,15 +1963,23 @@ impl std::fmt::Display for CreateRepoDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct UpdateRepoDiff {
     org: String,
     name: String,
+    #[serde(skip)]
     repo_node_id: String,
+    #[serde(skip)]
     repo_id: u64,
+    /// When the repo was last modified according to the state the plan was computed from. It's
+    /// bumped by unrelated activity, so it's only checked when applying and isn't serialized.
+    #[serde(skip)]
+    updated_at: Option<String>,
     // old, new
     settings_diff: (RepoSettings, RepoSettings),
     permission_diffs: Vec<RepoPermissionAssignmentDiff>,
+    /// Users whose invitation to collaborate wasn't accepted yet, and already matches the team repo
+    pending_invitations: Vec<String>,
     branch_protection_diffs: Vec<BranchProtectionDiff>,
     app_installation_diffs: Vec<AppInstallationDiff>,
 }
@@ -828,7 +2012,15 @@ impl UpdateRepoDiff {
         }
 
         if self.settings_diff.0 != self.settings_diff.1 {
-            sync.edit_repo(&self.org, &self.name, &self.settings_diff.1)?;
+            let current = sync.repo_updated_at(&self.org, &self.name)?;
+            if modified_since_plan(&self.updated_at, &current) {
+                warn!(
+                    "skipping the settings update of repo '{}/{}': it was modified on GitHub after the plan was computed",
+                    self.org, self.name
+                );
+            } else {
+                sync.edit_repo(&self.org, &self.name, &self.settings_diff.1)?;
+            }
         }
         for permission in &self.permission_diffs {
             permission.apply(sync, &self.org, &self.name)?;
@@ -890,6 +2082,9 @@ impl std::fmt::Display for UpdateRepoDiff {
         for permission_diff in &self.permission_diffs {
             write!(f, "{permission_diff}")?;
         }
+        for user in &self.pending_invitations {
+            writeln!(f, "  Pending collaborator invite: {user} (no action)")?;
+        }
         if !self.branch_protection_diffs.is_empty() {
             writeln!(f, "  Branch Protections:")?;
         }
@@ -907,7 +2102,7 @@ impl std::fmt::Display for UpdateRepoDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct RepoPermissionAssignmentDiff {
     collaborator: RepoCollaborator,
     diff: RepoPermissionDiff,
@@ -915,6 +2110,20 @@ struct RepoPermissionAssignmentDiff {
 
 impl RepoPermissionAssignmentDiff {
     fn apply(&self, sync: &GitHubWrite, org: &str, repo_name: &str) -> anyhow::Result<()> {
+        let collaborator = match &self.collaborator {
+            RepoCollaborator::Team(team_name) => format!("team '{team_name}'"),
+            RepoCollaborator::User(user_name) => format!("user '{user_name}'"),
+        };
+        self.apply_unannotated(sync, org, repo_name)
+            .with_context(|| format!("failed to change the permission of {collaborator}"))
+    }
+
+    fn apply_unannotated(
+        &self,
+        sync: &GitHubWrite,
+        org: &str,
+        repo_name: &str,
+    ) -> anyhow::Result<()> {
         match &self.diff {
             RepoPermissionDiff::Create(p) | RepoPermissionDiff::Update(_, p) => {
                 match &self.collaborator {
@@ -959,32 +2168,71 @@ impl std::fmt::Display for RepoPermissionAssignmentDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 enum RepoPermissionDiff {
     Create(RepoPermission),
     Update(RepoPermission, RepoPermission),
     Delete(RepoPermission),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize)]
 enum RepoCollaborator {
     Team(String),
     User(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct BranchProtectionDiff {
     pattern: String,
     operation: BranchProtectionDiffOperation,
+    /// The reduction of the required approving review count that is held, as `(current, expected)`
+    held_review_count_reduction: Option<(u8, u8)>,
 }
 
 impl BranchProtectionDiff {
+    /// The required approving review count before and after the diff, when the diff reduces it
+    fn review_count_reduction(&self) -> Option<(u8, u8)> {
+        match &self.operation {
+            BranchProtectionDiffOperation::Update(_, old, new)
+                if new.required_approving_review_count < old.required_approving_review_count =>
+            {
+                Some((
+                    old.required_approving_review_count,
+                    new.required_approving_review_count,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Keep the current required approving review count if the diff would reduce it
+    fn hold_review_count_reduction(&mut self) {
+        let reduction = self.review_count_reduction();
+        if let (Some((current, _)), BranchProtectionDiffOperation::Update(_, _, new)) =
+            (reduction, &mut self.operation)
+        {
+            new.required_approving_review_count = current;
+            self.held_review_count_reduction = reduction;
+        }
+    }
+
     fn apply(
         &self,
         sync: &GitHubWrite,
         org: &str,
         repo_name: &str,
         repo_id: &str,
+    ) -> anyhow::Result<()> {
+        self.apply_unannotated(sync, org, repo_name, repo_id)
+            .with_context(|| format!("failed to apply the branch protection '{}'", self.pattern))
+    }
+
+    fn apply_unannotated(
+        &self,
+        sync: &GitHubWrite,
+        org: &str,
+        repo_name: &str,
+        repo_id: &str,
     ) -> anyhow::Result<()> {
         match &self.operation {
             BranchProtectionDiffOperation::Create(bp) => {
@@ -994,6 +2242,8 @@ impl BranchProtectionDiff {
                     bp,
                 )?;
             }
+            // Nothing is left to change once a reduction of the review count is held
+            BranchProtectionDiffOperation::Update(_, old, bp) if old == bp => {}
             BranchProtectionDiffOperation::Update(id, _, bp) => {
                 sync.upsert_branch_protection(
                     BranchProtectionOp::UpdateBranchProtection(id.clone()),
@@ -1021,7 +2271,14 @@ impl std::fmt::Display for BranchProtectionDiff {
         match &self.operation {
             BranchProtectionDiffOperation::Create(bp) => log_branch_protection(bp, None, f),
             BranchProtectionDiffOperation::Update(_, old, new) => {
-                log_branch_protection(old, Some(new), f)
+                log_branch_protection(old, Some(new), &mut *f)?;
+                if let Some((current, expected)) = self.held_review_count_reduction {
+                    writeln!(
+                        f,
+                        "        Holding the reduction of required approvals: {current} => {expected} (weakening)"
+                    )?;
+                }
+                Ok(())
             }
             BranchProtectionDiffOperation::Delete(_) => {
                 writeln!(f, "        Deleting branch protection")
@@ -1052,24 +2309,37 @@ fn log_branch_protection(
         };
     }
 
+    log!("Enforce Admins", is_admin_enforced);
+    log!("Requires Approving Reviews", requires_approving_reviews);
     log!("Dismiss Stale Reviews", dismisses_stale_reviews);
-    log!(
-        "Required Approving Review Count",
-        required_approving_review_count
-    );
+    match new.map(|n| n.required_approving_review_count) {
+        Some(expected) if expected < current.required_approving_review_count => {
+            writeln!(
+                result,
+                "        Reducing required approvals: {} => {expected} (weakening)",
+                current.required_approving_review_count
+            )?;
+        }
+        _ => {
+            log!(
+                "Required Approving Review Count",
+                required_approving_review_count
+            );
+        }
+    }
     log!("Required Checks", required_status_check_contexts);
     log!("Allowances", push_allowances);
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 enum BranchProtectionDiffOperation {
     Create(api::BranchProtection),
     Update(String, api::BranchProtection, api::BranchProtection),
     Delete(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 enum AppInstallationDiff {
     Add(AppInstallation),
     Remove(AppInstallation),
@@ -1102,7 +2372,7 @@ impl std::fmt::Display for AppInstallationDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 enum TeamDiff {
     Create(CreateTeamDiff),
     Edit(EditTeamDiff),
@@ -1111,13 +2381,59 @@ enum TeamDiff {
 
 impl TeamDiff {
     fn apply(self, sync: &GitHubWrite) -> anyhow::Result<()> {
+        let team = format!("{}/{}", self.org(), self.name());
         match self {
-            TeamDiff::Create(c) => c.apply(sync)?,
-            TeamDiff::Edit(e) => e.apply(sync)?,
-            TeamDiff::Delete(d) => d.apply(sync)?,
+            TeamDiff::Create(c) => c.apply(sync),
+            TeamDiff::Edit(e) => e.apply(sync),
+            TeamDiff::Delete(d) => d.apply(sync),
         }
+        .with_context(|| format!("failed to apply the changes to team '{team}'"))
+    }
 
-        Ok(())
+    fn noop(&self) -> bool {
+        match self {
+            TeamDiff::Edit(e) => e.noop(),
+            TeamDiff::Create(_) | TeamDiff::Delete(_) => false,
+        }
+    }
+
+    fn org(&self) -> &str {
+        match self {
+            TeamDiff::Create(c) => &c.org,
+            TeamDiff::Edit(e) => &e.org,
+            TeamDiff::Delete(d) => &d.org,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            TeamDiff::Create(c) => &c.name,
+            TeamDiff::Edit(e) => &e.name,
+            TeamDiff::Delete(d) => &d.name,
+        }
+    }
+
+    /// The users whose membership of the team is changed by the diff
+    fn changed_members(&self) -> Vec<String> {
+        match self {
+            TeamDiff::Create(c) => c.members.iter().map(|(name, _)| name.clone()).collect(),
+            TeamDiff::Edit(e) => e
+                .member_diffs
+                .iter()
+                .filter(|(_, diff)| {
+                    !matches!(
+                        diff,
+                        MemberDiff::Noop
+                            | MemberDiff::HoldOutOfBand
+                            | MemberDiff::HeldRemoval
+                            | MemberDiff::SkipDenied
+                            | MemberDiff::KeepTokenOwner
+                    )
+                })
+                .map(|(name, _)| name.clone())
+                .collect(),
+            TeamDiff::Delete(_) => Vec::new(),
+        }
     }
 }
 
@@ -1131,13 +2447,15 @@ impl std::fmt::Display for TeamDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct CreateTeamDiff {
     org: String,
     name: String,
     description: String,
     privacy: TeamPrivacy,
     members: Vec<(String, TeamRole)>,
+    /// The teams of the team repo defining this GitHub team, when there's more than one
+    duplicate_definitions: Vec<String>,
 }
 
 impl CreateTeamDiff {
@@ -1165,6 +2483,13 @@ impl std::fmt::Display for CreateTeamDiff {
                 TeamPrivacy::Closed => "closed",
             }
         )?;
+        if !self.duplicate_definitions.is_empty() {
+            writeln!(
+                f,
+                "  Defined by several teams: {}",
+                self.duplicate_definitions.join(", ")
+            )?;
+        }
         writeln!(f, "  Members:")?;
         for (name, role) in &self.members {
             writeln!(f, "    {name}: {role}")?;
@@ -1173,14 +2498,22 @@ impl std::fmt::Display for CreateTeamDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct EditTeamDiff {
     org: String,
     name: String,
+    /// When the team was last modified according to the state the plan was computed from. It's
+    /// only checked when applying and isn't serialized, like the one of repos.
+    #[serde(skip)]
+    updated_at: Option<String>,
     name_diff: Option<String>,
     description_diff: Option<(String, String)>,
     privacy_diff: Option<(TeamPrivacy, TeamPrivacy)>,
     member_diffs: Vec<(String, MemberDiff)>,
+    /// The teams of the team repo defining this GitHub team, when there's more than one
+    duplicate_definitions: Vec<String>,
+    /// The IdP groups the members of the team are synchronized from, leaving them alone
+    idp_groups: Vec<String>,
 }
 
 impl EditTeamDiff {
@@ -1189,13 +2522,21 @@ impl EditTeamDiff {
             || self.description_diff.is_some()
             || self.privacy_diff.is_some()
         {
-            sync.edit_team(
-                &self.org,
-                &self.name,
-                self.name_diff.as_deref(),
-                self.description_diff.as_ref().map(|(_, d)| d.as_str()),
-                self.privacy_diff.map(|(_, p)| p),
-            )?;
+            let current = sync.team_updated_at(&self.org, &self.name)?;
+            if modified_since_plan(&self.updated_at, &current) {
+                warn!(
+                    "skipping the edit of team '{}/{}': it was modified on GitHub after the plan was computed",
+                    self.org, self.name
+                );
+            } else {
+                sync.edit_team(
+                    &self.org,
+                    &self.name,
+                    self.name_diff.as_deref(),
+                    self.description_diff.as_ref().map(|(_, d)| d.as_str()),
+                    self.privacy_diff.map(|(_, p)| p),
+                )?;
+            }
         }
 
         for (member_name, member_diff) in self.member_diffs {
@@ -1205,6 +2546,50 @@ impl EditTeamDiff {
         Ok(())
     }
 
+    /// Whether the team has members on GitHub but none in the team repo, which usually means that
+    /// something is wrong with the team data rather than that the team was emptied on purpose.
+    fn resolved_to_no_members(&self) -> bool {
+        !self.member_diffs.is_empty()
+            && self.member_diffs.iter().all(|(_, d)| {
+                matches!(
+                    d,
+                    MemberDiff::Delete
+                        | MemberDiff::DeleteOutOfBand
+                        | MemberDiff::HoldOutOfBand
+                        | MemberDiff::HeldRemoval
+                        | MemberDiff::DeleteDenied
+                        | MemberDiff::KeepTokenOwner
+                )
+            })
+    }
+
+    /// The percentage of the members currently on GitHub that the diff removes
+    fn removal_percent(&self) -> usize {
+        let current = self
+            .member_diffs
+            .iter()
+            .filter(|(_, d)| !matches!(d, MemberDiff::Create(_)))
+            .count();
+        let removed = self
+            .member_diffs
+            .iter()
+            .filter(|(_, d)| {
+                matches!(
+                    d,
+                    MemberDiff::Delete
+                        | MemberDiff::DeleteOutOfBand
+                        | MemberDiff::HeldRemoval
+                        | MemberDiff::DeleteDenied
+                )
+            })
+            .count();
+        if current == 0 {
+            0
+        } else {
+            removed * 100 / current
+        }
+    }
+
     fn noop(&self) -> bool {
         self.name_diff.is_none()
             && self.description_diff.is_none()
@@ -1215,10 +2600,24 @@ impl EditTeamDiff {
 
 impl std::fmt::Display for EditTeamDiff {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.noop() {
+        if self.noop() && self.duplicate_definitions.is_empty() && self.idp_groups.is_empty() {
             return Ok(());
         }
         writeln!(f, "📝 Editing team '{}/{}':", self.org, self.name)?;
+        if !self.idp_groups.is_empty() {
+            writeln!(
+                f,
+                "  Externally managed: members synchronized from the IdP groups {}",
+                self.idp_groups.join(", ")
+            )?;
+        }
+        if !self.duplicate_definitions.is_empty() {
+            writeln!(
+                f,
+                "  Defined by several teams: {}",
+                self.duplicate_definitions.join(", ")
+            )?;
+        }
         if let Some(n) = &self.name_diff {
             writeln!(f, "  New name: {n}")?;
         }
@@ -1232,6 +2631,23 @@ impl std::fmt::Display for EditTeamDiff {
             };
             writeln!(f, "  New privacy: '{}' => '{}'", display(old), display(new))?;
         }
+        if self.resolved_to_no_members() {
+            writeln!(
+                f,
+                "  The team has no members in the team repo, check the team data"
+            )?;
+        }
+        if self
+            .member_diffs
+            .iter()
+            .any(|(_, d)| matches!(d, MemberDiff::HeldRemoval))
+        {
+            writeln!(
+                f,
+                "  {}% of the members would be removed, held pending confirmation",
+                self.removal_percent()
+            )?;
+        }
         for (member, diff) in &self.member_diffs {
             match diff {
                 MemberDiff::Create(r) => {
@@ -1243,6 +2659,36 @@ impl std::fmt::Display for EditTeamDiff {
                 MemberDiff::Delete => {
                     writeln!(f, "  Deleting member '{member}'")?;
                 }
+                MemberDiff::DeleteOutOfBand => {
+                    writeln!(
+                        f,
+                        "  Deleting member '{member}' (added out-of-band, not in the team repo)"
+                    )?;
+                }
+                MemberDiff::HoldOutOfBand => {
+                    writeln!(
+                        f,
+                        "  Holding the removal of member '{member}' for review (added out-of-band, not in the team repo)"
+                    )?;
+                }
+                MemberDiff::HeldRemoval => {
+                    writeln!(f, "  Holding the removal of member '{member}'")?;
+                }
+                MemberDiff::DeleteDenied => {
+                    writeln!(f, "  Deleting member '{member}' (on the deny list)")?;
+                }
+                MemberDiff::SkipDenied => {
+                    writeln!(
+                        f,
+                        "  Not adding member '{member}' (on the deny list, but in the team repo)"
+                    )?;
+                }
+                MemberDiff::KeepTokenOwner => {
+                    writeln!(
+                        f,
+                        "  Keeping member '{member}' as is (the sync runs as them, despite the team repo)"
+                    )?;
+                }
                 MemberDiff::Noop => {}
             }
         }
@@ -1250,25 +2696,66 @@ impl std::fmt::Display for EditTeamDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 enum MemberDiff {
     Create(TeamRole),
     ChangeRole((TeamRole, TeamRole)),
+    /// The member left the team, and is one of its alumni or still in the team repo otherwise
     Delete,
+    /// The member isn't anywhere in the team repo, neither in a team nor among the alumni, so
+    /// they were added directly on GitHub
+    DeleteOutOfBand,
+    /// Like `DeleteOutOfBand`, but the removal is held for manual review
+    HoldOutOfBand,
+    /// The member would be removed, but too many members of the team would be removed at once
+    HeldRemoval,
+    /// The member is on the deny list, and is removed regardless of the team repo
+    DeleteDenied,
+    /// The member is in the team repo, but isn't added as they're on the deny list
+    SkipDenied,
+    /// The member would be removed or demoted, but is the account the sync runs as
+    KeepTokenOwner,
     Noop,
 }
 
 impl MemberDiff {
     fn apply(self, org: &str, team: &str, member: &str, sync: &GitHubWrite) -> anyhow::Result<()> {
-        match self {
-            MemberDiff::Create(role) | MemberDiff::ChangeRole((_, role)) => {
+        let (operation, reason) = match self {
+            MemberDiff::Create(role) => {
+                sync.add_team_membership(org, team, member, role)?;
+                ("add", "member of the team in the team repo")
+            }
+            MemberDiff::ChangeRole((_, role)) => {
                 sync.set_team_membership(org, team, member, role)?;
+                ("change-role", "role of the member in the team repo")
             }
-            MemberDiff::Delete => sync.remove_team_membership(org, team, member)?,
-            MemberDiff::Noop => {}
-        }
-
-        Ok(())
+            MemberDiff::Delete => {
+                sync.remove_team_membership(org, team, member)?;
+                ("remove", "left the team in the team repo")
+            }
+            MemberDiff::DeleteOutOfBand => {
+                sync.remove_team_membership(org, team, member)?;
+                ("remove", "not in the team repo, added out-of-band")
+            }
+            MemberDiff::DeleteDenied => {
+                sync.remove_team_membership(org, team, member)?;
+                ("remove", "on the deny list")
+            }
+            MemberDiff::HoldOutOfBand
+            | MemberDiff::HeldRemoval
+            | MemberDiff::SkipDenied
+            | MemberDiff::KeepTokenOwner
+            | MemberDiff::Noop => {
+                return Ok(());
+            }
+        };
+        sync.audit(&audit::AuditEntry {
+            operation,
+            org,
+            team,
+            member,
+            reason,
+        })
     }
 
     fn is_noop(&self) -> bool {
@@ -1276,15 +2763,22 @@ impl MemberDiff {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 struct DeleteTeamDiff {
     org: String,
     name: String,
     slug: String,
+    /// The teams nested in this one on GitHub that are kept, as `(name, slug)`. GitHub deletes
+    /// them along with their parent when an org owner deletes it, so they are moved to the top of
+    /// the org first.
+    child_teams: Vec<(String, String)>,
 }
 
 impl DeleteTeamDiff {
     fn apply(self, sync: &GitHubWrite) -> anyhow::Result<()> {
+        for (_, child_slug) in &self.child_teams {
+            sync.detach_team(&self.org, child_slug)?;
+        }
         sync.delete_team(&self.org, &self.slug)?;
         Ok(())
     }
@@ -1293,6 +2787,19 @@ impl DeleteTeamDiff {
 impl std::fmt::Display for DeleteTeamDiff {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "❌ Deleting team '{}/{}'", self.org, self.name)?;
+        if !self.child_teams.is_empty() {
+            let names = self
+                .child_teams
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "  ⚠️ Moving its child teams to the top of the org first, so that they aren't \
+                 deleted with it: {}",
+                names.join(", ")
+            )?;
+        }
         Ok(())
     }
 }
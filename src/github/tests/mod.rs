@@ -1,4 +1,8 @@
+use crate::github::api::{self, TeamPrivacy, TeamRole};
+use crate::github::audit::{AuditEntry, AuditLog};
 use crate::github::tests::test_utils::{BranchProtectionBuilder, DataModel, RepoData, TeamData};
+use crate::github::transactions::{read_transactions, Transaction, TransactionLog};
+use crate::github::{epoch_days, usernames};
 use rust_team_data::v1::{BranchProtectionMode, RepoPermission};
 
 mod test_utils;
@@ -29,14 +33,15 @@ fn team_create() {
                 privacy: Closed,
                 members: [
                     (
-                        "mark",
+                        "jan",
                         Member,
                     ),
                     (
-                        "jan",
+                        "mark",
                         Member,
                     ),
                 ],
+                duplicate_definitions: [],
             },
         ),
     ]
@@ -59,27 +64,70 @@ fn team_add_member() {
             EditTeamDiff {
                 org: "rust-lang",
                 name: "admins-gh",
+                updated_at: None,
                 name_diff: None,
                 description_diff: None,
                 privacy_diff: None,
                 member_diffs: [
-                    (
-                        "mark",
-                        Noop,
-                    ),
                     (
                         "jan",
                         Create(
                             Member,
                         ),
                     ),
+                    (
+                        "mark",
+                        Noop,
+                    ),
                 ],
+                duplicate_definitions: [],
+                idp_groups: [],
             },
         ),
     ]
     "###);
 }
 
+#[test]
+fn team_dont_add_member_if_org_invitation_is_pending() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let mut gh = model.gh_model();
+
+    model.get_team("admins").add_gh_member("admins-gh", user2);
+    gh.add_org_invitation("jan");
+
+    let team_diff = model.diff_teams(gh);
+    insta::assert_debug_snapshot!(team_diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [
+                    (
+                        "jan",
+                        Noop,
+                    ),
+                    (
+                        "mark",
+                        Noop,
+                    ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+    ]
+    "#);
+}
+
 #[test]
 fn team_dont_add_member_if_invitation_is_pending() {
     let mut model = DataModel::default();
@@ -98,19 +146,22 @@ fn team_dont_add_member_if_invitation_is_pending() {
             EditTeamDiff {
                 org: "rust-lang",
                 name: "admins-gh",
+                updated_at: None,
                 name_diff: None,
                 description_diff: None,
                 privacy_diff: None,
                 member_diffs: [
                     (
-                        "mark",
+                        "jan",
                         Noop,
                     ),
                     (
-                        "jan",
+                        "mark",
                         Noop,
                     ),
                 ],
+                duplicate_definitions: [],
+                idp_groups: [],
             },
         ),
     ]
@@ -136,23 +187,153 @@ fn team_remove_member() {
             EditTeamDiff {
                 org: "rust-lang",
                 name: "admins-gh",
+                updated_at: None,
                 name_diff: None,
                 description_diff: None,
                 privacy_diff: None,
                 member_diffs: [
+                    (
+                        "jan",
+                        DeleteOutOfBand,
+                    ),
                     (
                         "mark",
                         Noop,
                     ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+    ]
+    "###);
+}
+
+#[test]
+fn team_remove_alumni_member() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    let team = model.get_team("admins");
+    team.remove_gh_member("admins-gh", user2);
+    team.add_alumni(user2);
+
+    let team_diff = model.diff_teams(gh);
+    insta::assert_debug_snapshot!(team_diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [
                     (
                         "jan",
                         Delete,
                     ),
+                    (
+                        "mark",
+                        Noop,
+                    ),
                 ],
+                duplicate_definitions: [],
+                idp_groups: [],
             },
         ),
     ]
-    "###);
+    "#);
+}
+
+#[test]
+fn team_resolved_to_no_members() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let gh = model.gh_model();
+
+    let team = model.get_team("admins");
+    team.remove_gh_member("admins-gh", user);
+    team.add_alumni(user);
+
+    insta::assert_snapshot!(model.diff_all(gh), @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      The team has no members in the team repo, check the team data
+      Deleting member 'mark'
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn team_defined_by_several_teams() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    let user3 = model.create_user("sofia");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    model.create_team(TeamData::new("leads").gh_team("admins-gh", &[user2, user3]));
+
+    insta::assert_snapshot!(model.diff_all(gh), @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      Defined by several teams: admins, leads
+      Adding member 'sofia' with member role
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn team_hold_out_of_band_member() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    model
+        .get_team("admins")
+        .remove_gh_member("admins-gh", user2);
+
+    let mut diff = model.diff_all(gh);
+    diff.hold_out_of_band_removals();
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      Holding the removal of member 'jan' for review (added out-of-band, not in the team repo)
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn team_dont_hold_member_still_in_team_repo() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    model.create_team(TeamData::new("users").gh_team("users-gh", &[user2]));
+    let gh = model.gh_model();
+
+    // Left the team without becoming one of its alumni, but is still in another team
+    model
+        .get_team("admins")
+        .remove_gh_member("admins-gh", user2);
+
+    let mut diff = model.diff_all(gh);
+    diff.hold_out_of_band_removals();
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      Deleting member 'jan'
+    💻 Repo Diffs:
+    "#);
 }
 
 #[test]
@@ -178,6 +359,7 @@ fn team_delete() {
             EditTeamDiff {
                 org: "rust-lang",
                 name: "admins-gh",
+                updated_at: None,
                 name_diff: None,
                 description_diff: None,
                 privacy_diff: None,
@@ -187,6 +369,8 @@ fn team_delete() {
                         Noop,
                     ),
                 ],
+                duplicate_definitions: [],
+                idp_groups: [],
             },
         ),
         Delete(
@@ -194,12 +378,103 @@ fn team_delete() {
                 org: "rust-lang",
                 name: "users-gh",
                 slug: "users-gh",
+                child_teams: [],
             },
         ),
     ]
     "###);
 }
 
+#[test]
+fn team_delete_parent_of_kept_teams() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(
+        TeamData::new("admins")
+            .gh_team("admins-gh", &[user])
+            .gh_team("users-gh", &[user])
+            .gh_team("reviewers-gh", &[user]),
+    );
+    let mut gh = model.gh_model();
+    gh.set_team_parent("admins-gh", "users-gh");
+    gh.set_team_parent("reviewers-gh", "users-gh");
+
+    model.get_team("admins").remove_gh_team("users-gh");
+    model.get_team("admins").remove_gh_team("reviewers-gh");
+
+    let diff = model.diff_all(gh);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    ❌ Deleting team 'rust-lang/reviewers-gh'
+    ❌ Deleting team 'rust-lang/users-gh'
+      ⚠️ Moving its child teams to the top of the org first, so that they aren't deleted with it: admins-gh
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn team_edit_only_changed_fields() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let mut gh = model.gh_model();
+    gh.set_team_privacy("admins-gh", TeamPrivacy::Secret);
+
+    // Only the privacy is sent to GitHub, the name and the description are left untouched
+    let team_diff = model.diff_teams(gh);
+    insta::assert_debug_snapshot!(team_diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: Some(
+                    (
+                        Secret,
+                        Closed,
+                    ),
+                ),
+                member_diffs: [
+                    (
+                        "mark",
+                        Noop,
+                    ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+    ]
+    "#);
+}
+
+#[test]
+fn team_privacy_change_keeps_members() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let mut gh = model.gh_model();
+    gh.set_team_privacy("admins-gh", TeamPrivacy::Secret);
+
+    // Changing the privacy is a single team edit, without any membership operation
+    let diff = model.diff_all(gh);
+    insta::assert_debug_snapshot!(diff.estimated_writes(), @r#"
+    {
+        "teams": 1,
+    }
+    "#);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      New privacy: 'secret' => 'closed'
+    💻 Repo Diffs:
+    "#);
+}
+
 #[test]
 fn repo_noop() {
     let model = DataModel::default();
@@ -224,6 +499,7 @@ fn repo_change_description() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -243,6 +519,7 @@ fn repo_change_description() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -267,6 +544,7 @@ fn repo_change_homepage() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -290,6 +568,7 @@ fn repo_change_homepage() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -298,6 +577,19 @@ fn repo_change_homepage() {
     "#);
 }
 
+#[test]
+fn repo_diffs_keep_their_order_when_concurrent() {
+    let mut model = DataModel::default();
+    let gh = model.gh_model();
+    let names = (0..10).map(|i| format!("repo{i}")).collect::<Vec<_>>();
+    for name in &names {
+        model.create_repo(RepoData::new(name));
+    }
+
+    let diff = model.diff_repos_concurrently(gh, 4);
+    assert_eq!(diff.iter().map(|d| d.name()).collect::<Vec<_>>(), names);
+}
+
 #[test]
 fn repo_create() {
     let mut model = DataModel::default();
@@ -394,6 +686,7 @@ fn repo_add_member() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -422,6 +715,7 @@ fn repo_add_member() {
                         ),
                     },
                 ],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -431,17 +725,18 @@ fn repo_add_member() {
 }
 
 #[test]
-fn repo_change_member_permissions() {
+fn repo_pending_collaborator_invitation() {
     let mut model = DataModel::default();
-    model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Write));
+    model.create_repo(RepoData::new("repo1"));
 
-    let gh = model.gh_model();
+    let mut gh = model.gh_model();
     model
         .get_repo("repo1")
-        .members
-        .last_mut()
-        .unwrap()
-        .permission = RepoPermission::Triage;
+        .add_member("user1", RepoPermission::Write);
+    model
+        .get_repo("repo1")
+        .add_member("user2", RepoPermission::Admin);
+    gh.add_repo_invitation("repo1", "user1", RepoPermission::Write);
 
     let diff = model.diff_repos(gh);
     insta::assert_debug_snapshot!(diff, @r#"
@@ -452,6 +747,7 @@ fn repo_change_member_permissions() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -473,29 +769,42 @@ fn repo_change_member_permissions() {
                 permission_diffs: [
                     RepoPermissionAssignmentDiff {
                         collaborator: User(
-                            "user1",
+                            "user2",
                         ),
-                        diff: Update(
-                            Write,
-                            Triage,
+                        diff: Create(
+                            Admin,
                         ),
                     },
                 ],
+                pending_invitations: [
+                    "user1",
+                ],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
         ),
     ]
     "#);
+    insta::assert_snapshot!(diff[0].to_string(), @r#"
+    📝 Editing repo 'rust-lang/repo1':
+      Permission Changes:
+        Giving user 'user2' admin permission
+      Pending collaborator invite: user1 (no action)
+    "#);
 }
 
 #[test]
-fn repo_remove_member() {
+fn repo_change_member_permissions() {
     let mut model = DataModel::default();
     model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Write));
 
     let gh = model.gh_model();
-    model.get_repo("repo1").members.clear();
+    model
+        .get_repo("repo1")
+        .members
+        .last_mut()
+        .unwrap()
+        .permission = RepoPermission::Triage;
 
     let diff = model.diff_repos(gh);
     insta::assert_debug_snapshot!(diff, @r#"
@@ -506,6 +815,7 @@ fn repo_remove_member() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -529,11 +839,13 @@ fn repo_remove_member() {
                         collaborator: User(
                             "user1",
                         ),
-                        diff: Delete(
+                        diff: Update(
                             Write,
+                            Triage,
                         ),
                     },
                 ],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -543,14 +855,17 @@ fn repo_remove_member() {
 }
 
 #[test]
-fn repo_add_team() {
+fn repo_upgrade_member_permissions() {
     let mut model = DataModel::default();
-    model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Write));
+    model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Triage));
 
     let gh = model.gh_model();
     model
         .get_repo("repo1")
-        .add_team("team1", RepoPermission::Triage);
+        .members
+        .last_mut()
+        .unwrap()
+        .permission = RepoPermission::Write;
 
     let diff = model.diff_repos(gh);
     insta::assert_debug_snapshot!(diff, @r#"
@@ -561,6 +876,7 @@ fn repo_add_team() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -581,14 +897,16 @@ fn repo_add_team() {
                 ),
                 permission_diffs: [
                     RepoPermissionAssignmentDiff {
-                        collaborator: Team(
-                            "team1",
+                        collaborator: User(
+                            "user1",
                         ),
-                        diff: Create(
+                        diff: Update(
                             Triage,
+                            Write,
                         ),
                     },
                 ],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -598,12 +916,12 @@ fn repo_add_team() {
 }
 
 #[test]
-fn repo_change_team_permissions() {
+fn repo_remove_member() {
     let mut model = DataModel::default();
-    model.create_repo(RepoData::new("repo1").team("team1", RepoPermission::Triage));
+    model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Write));
 
     let gh = model.gh_model();
-    model.get_repo("repo1").teams.last_mut().unwrap().permission = RepoPermission::Admin;
+    model.get_repo("repo1").members.clear();
 
     let diff = model.diff_repos(gh);
     insta::assert_debug_snapshot!(diff, @r#"
@@ -614,6 +932,7 @@ fn repo_change_team_permissions() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -634,15 +953,15 @@ fn repo_change_team_permissions() {
                 ),
                 permission_diffs: [
                     RepoPermissionAssignmentDiff {
-                        collaborator: Team(
-                            "team1",
+                        collaborator: User(
+                            "user1",
                         ),
-                        diff: Update(
-                            Triage,
-                            Admin,
+                        diff: Delete(
+                            Write,
                         ),
                     },
                 ],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -651,6 +970,137 @@ fn repo_change_team_permissions() {
     "#);
 }
 
+#[test]
+fn repo_add_team() {
+    let mut model = DataModel::default();
+    model.create_repo(RepoData::new("repo1").member("user1", RepoPermission::Write));
+
+    let gh = model.gh_model();
+    model
+        .get_repo("repo1")
+        .add_team("team1", RepoPermission::Triage);
+
+    let diff = model.diff_repos(gh);
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Update(
+            UpdateRepoDiff {
+                org: "rust-lang",
+                name: "repo1",
+                repo_node_id: "0",
+                repo_id: 0,
+                updated_at: None,
+                settings_diff: (
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                ),
+                permission_diffs: [
+                    RepoPermissionAssignmentDiff {
+                        collaborator: Team(
+                            "team1",
+                        ),
+                        diff: Create(
+                            Triage,
+                        ),
+                    },
+                ],
+                pending_invitations: [],
+                branch_protection_diffs: [],
+                app_installation_diffs: [],
+            },
+        ),
+    ]
+    "#);
+}
+
+#[test]
+fn repo_change_team_permissions() {
+    let mut model = DataModel::default();
+    model.create_repo(RepoData::new("repo1").team("team1", RepoPermission::Triage));
+
+    let gh = model.gh_model();
+    model.get_repo("repo1").teams.last_mut().unwrap().permission = RepoPermission::Admin;
+
+    let diff = model.diff_repos(gh);
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Update(
+            UpdateRepoDiff {
+                org: "rust-lang",
+                name: "repo1",
+                repo_node_id: "0",
+                repo_id: 0,
+                updated_at: None,
+                settings_diff: (
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                ),
+                permission_diffs: [
+                    RepoPermissionAssignmentDiff {
+                        collaborator: Team(
+                            "team1",
+                        ),
+                        diff: Update(
+                            Triage,
+                            Admin,
+                        ),
+                    },
+                ],
+                pending_invitations: [],
+                branch_protection_diffs: [],
+                app_installation_diffs: [],
+            },
+        ),
+    ]
+    "#);
+}
+
+#[test]
+fn repo_downgrade_team_permissions() {
+    let mut model = DataModel::default();
+    model.create_repo(RepoData::new("repo1").team("team1", RepoPermission::Maintain));
+
+    let gh = model.gh_model();
+    model.get_repo("repo1").teams.last_mut().unwrap().permission = RepoPermission::Write;
+
+    let diff = model.diff_all(gh);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      Permission Changes:
+        Changing team 'team1''s permission from maintain to write
+    "#);
+}
+
 #[test]
 fn repo_remove_team() {
     let mut model = DataModel::default();
@@ -668,6 +1118,7 @@ fn repo_remove_team() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -696,6 +1147,7 @@ fn repo_remove_team() {
                         ),
                     },
                 ],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -721,6 +1173,7 @@ fn repo_archive_repo() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -740,6 +1193,7 @@ fn repo_archive_repo() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [],
                 app_installation_diffs: [],
             },
@@ -768,6 +1222,7 @@ fn repo_add_branch_protection() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -787,6 +1242,7 @@ fn repo_add_branch_protection() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [
                     BranchProtectionDiff {
                         pattern: "master",
@@ -804,6 +1260,7 @@ fn repo_add_branch_protection() {
                                 requires_approving_reviews: true,
                             },
                         ),
+                        held_review_count_reduction: None,
                     },
                     BranchProtectionDiff {
                         pattern: "beta",
@@ -818,6 +1275,7 @@ fn repo_add_branch_protection() {
                                 requires_approving_reviews: false,
                             },
                         ),
+                        held_review_count_reduction: None,
                     },
                 ],
                 app_installation_diffs: [],
@@ -868,6 +1326,7 @@ fn repo_update_branch_protection() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -887,6 +1346,7 @@ fn repo_update_branch_protection() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [
                     BranchProtectionDiff {
                         pattern: "master",
@@ -916,6 +1376,7 @@ fn repo_update_branch_protection() {
                                 requires_approving_reviews: true,
                             },
                         ),
+                        held_review_count_reduction: None,
                     },
                 ],
                 app_installation_diffs: [],
@@ -925,6 +1386,36 @@ fn repo_update_branch_protection() {
     "#);
 }
 
+#[test]
+fn repo_update_branch_protection_display() {
+    let mut model = DataModel::default();
+    model.create_repo(
+        RepoData::new("repo1")
+            .team("team1", RepoPermission::Write)
+            .branch_protections(vec![BranchProtectionBuilder::pr_required(
+                "master",
+                &["test"],
+                1,
+            )
+            .build()]),
+    );
+
+    let gh = model.gh_model();
+    model.get_repo("repo1").branch_protections[0].mode = BranchProtectionMode::PrNotRequired;
+
+    let diff = model.diff_all(gh);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      Branch Protections:
+          master
+            Requires Approving Reviews: true => false
+            Reducing required approvals: 1 => 0 (weakening)
+            Required Checks: ["test"] => []
+    "#);
+}
+
 #[test]
 fn repo_remove_branch_protection() {
     let mut model = DataModel::default();
@@ -949,6 +1440,7 @@ fn repo_remove_branch_protection() {
                 name: "repo1",
                 repo_node_id: "0",
                 repo_id: 0,
+                updated_at: None,
                 settings_diff: (
                     RepoSettings {
                         description: Some(
@@ -968,12 +1460,14 @@ fn repo_remove_branch_protection() {
                     },
                 ),
                 permission_diffs: [],
+                pending_invitations: [],
                 branch_protection_diffs: [
                     BranchProtectionDiff {
                         pattern: "stable",
                         operation: Delete(
                             "1",
                         ),
+                        held_review_count_reduction: None,
                     },
                 ],
                 app_installation_diffs: [],
@@ -982,3 +1476,757 @@ fn repo_remove_branch_protection() {
     ]
     "#);
 }
+
+#[test]
+fn unmanaged_entities() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    let user3 = model.create_user("sakura");
+    model.create_team(
+        TeamData::new("admins")
+            .gh_team("admins-gh", &[user, user2, user3])
+            .gh_team("users-gh", &[user]),
+    );
+    model.create_repo(
+        RepoData::new("repo1")
+            .team("admins-gh", RepoPermission::Write)
+            .team("users-gh", RepoPermission::Triage)
+            .member("jan", RepoPermission::Triage),
+    );
+    let gh = model.gh_model();
+
+    model
+        .get_team("admins")
+        .remove_gh_member("admins-gh", user2);
+    // Offboarded through the team repo, so not drift
+    model
+        .get_team("admins")
+        .remove_gh_member("admins-gh", user3);
+    model.get_team("admins").add_alumni(user3);
+    model.get_team("admins").remove_gh_team("users-gh");
+    model.get_repo("repo1").members.clear();
+    model
+        .get_repo("repo1")
+        .teams
+        .retain(|t| t.name != "users-gh");
+
+    let diff = model.diff_all(gh);
+    insta::assert_debug_snapshot!(diff.unmanaged_entities(), @r#"
+    [
+        "member 'jan' of team 'rust-lang/admins-gh'",
+        "team 'rust-lang/users-gh'",
+        "collaborator 'jan' on repo 'rust-lang/repo1'",
+    ]
+    "#);
+}
+
+#[test]
+fn usernames_from_cache() {
+    let mut model = DataModel::default();
+    let mark = model.create_user("mark");
+    let jan = model.create_user("jan");
+    let gh = model.gh_model();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("usernames.json");
+    // The fresh entry is used as is, while the expired one is resolved again.
+    std::fs::write(
+        &path,
+        format!(
+            r#"{{
+                "{mark}": {{ "login": "mark-cached", "resolved_at": {} }},
+                "{jan}": {{ "login": "jan-expired", "resolved_at": 0 }}
+            }}"#,
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        ),
+    )
+    .unwrap();
+
+    let usernames = usernames::resolve_cached(&gh, &[mark, jan], &path).unwrap();
+    let mut usernames = usernames.into_iter().collect::<Vec<_>>();
+    usernames.sort();
+    insta::assert_debug_snapshot!(usernames, @r#"
+    [
+        (
+            0,
+            "mark-cached",
+        ),
+        (
+            1,
+            "jan",
+        ),
+    ]
+    "#);
+}
+
+#[test]
+fn unmanaged_repo_remove_team_grants() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    model.create_repo(RepoData::new("repo1").team("admins-gh", RepoPermission::Write));
+    model.create_repo(RepoData::new("repo2").team("admins-gh", RepoPermission::Write));
+    let gh = model.gh_model();
+
+    model.remove_repo("repo1");
+
+    let diff = model.diff_unmanaged_repos(gh);
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Update(
+            UpdateRepoDiff {
+                org: "rust-lang",
+                name: "repo1",
+                repo_node_id: "0",
+                repo_id: 0,
+                updated_at: None,
+                settings_diff: (
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                    RepoSettings {
+                        description: Some(
+                            "",
+                        ),
+                        homepage: None,
+                        archived: false,
+                        auto_merge_enabled: false,
+                    },
+                ),
+                permission_diffs: [
+                    RepoPermissionAssignmentDiff {
+                        collaborator: Team(
+                            "admins-gh",
+                        ),
+                        diff: Delete(
+                            Write,
+                        ),
+                    },
+                ],
+                pending_invitations: [],
+                branch_protection_diffs: [],
+                app_installation_diffs: [],
+            },
+        ),
+    ]
+    "#);
+}
+
+/// Runs the whole planner on a fixed model and compares the JSON plan with the golden file in
+/// `snapshots/`, so that any change to the planner's output has to be reviewed.
+#[test]
+fn golden_plan() {
+    let mut model = DataModel::default();
+    let mark = model.create_user("mark");
+    let jan = model.create_user("jan");
+    let sofia = model.create_user("sofia");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[mark, jan]));
+    model.create_team(TeamData::new("users").gh_team("users-gh", &[mark]));
+    model.create_repo(
+        RepoData::new("repo1")
+            .team("admins-gh", RepoPermission::Write)
+            .member("mark", RepoPermission::Triage)
+            .branch_protections(vec![BranchProtectionBuilder::pr_required(
+                "main",
+                &["test"],
+                1,
+            )
+            .build()]),
+    );
+    let gh = model.gh_model();
+
+    let admins = model.get_team("admins");
+    admins.remove_gh_member("admins-gh", jan);
+    admins.add_alumni(jan);
+    admins.add_gh_member("admins-gh", sofia);
+    model.get_team("admins").remove_gh_team("users-gh");
+    model.create_team(TeamData::new("reviewers").gh_team("reviewers-gh", &[sofia]));
+    let repo = model.get_repo("repo1");
+    repo.description = "The first repo".to_string();
+    repo.members.clear();
+    repo.add_member("sofia", RepoPermission::Maintain);
+    repo.branch_protections[0].dismiss_stale_review = true;
+    model.create_repo(RepoData::new("repo2").team("reviewers-gh", RepoPermission::Triage));
+
+    let diff = model.diff_all(gh);
+    insta::assert_snapshot!(serde_json::to_string_pretty(&diff).unwrap());
+}
+
+#[test]
+fn estimated_writes() {
+    let mut model = DataModel::default();
+    let mark = model.create_user("mark");
+    let jan = model.create_user("jan");
+    let sofia = model.create_user("sofia");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[mark, jan]));
+    model.create_repo(RepoData::new("repo1").team("admins-gh", RepoPermission::Write));
+    let gh = model.gh_model();
+
+    let admins = model.get_team("admins");
+    admins.remove_gh_member("admins-gh", jan);
+    admins.add_gh_member("admins-gh", sofia);
+    model.create_team(TeamData::new("reviewers").gh_team("reviewers-gh", &[sofia]));
+    let repo = model.get_repo("repo1");
+    repo.description = "The first repo".to_string();
+    repo.add_member("sofia", RepoPermission::Maintain);
+
+    let diff = model.diff_all(gh);
+    insta::assert_debug_snapshot!(diff.estimated_writes(), @r#"
+    {
+        "repo permissions": 1,
+        "repos": 1,
+        "team memberships": 3,
+        "teams": 1,
+    }
+    "#);
+}
+
+#[test]
+fn invitation_epoch_days() {
+    assert_eq!(epoch_days("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(epoch_days("2000-03-01T12:00:00Z"), Some(11017));
+    assert_eq!(epoch_days("2024-02-29T23:59:59+02:00"), Some(19782));
+    assert_eq!(epoch_days("not a date"), None);
+}
+
+#[test]
+fn confirm_each_change() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    model.create_repo(RepoData::new("repo1"));
+    let gh = model.gh_model();
+
+    model.create_team(TeamData::new("reviewers").gh_team("reviewers-gh", &[user]));
+    model.get_repo("repo1").description = "The first repo".to_string();
+
+    let mut diff = model.diff_all(gh);
+    let skipped = diff.confirm_each(|change| change.contains("repo1"));
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].contains("reviewers-gh"));
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      New description: '' => 'The first repo'
+    "#);
+}
+
+#[test]
+fn org_members_email_domains() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let mut gh = model.gh_model();
+    gh.add_verified_email("mark", "mark@rust-lang.org");
+    gh.add_verified_email("jan", "jan@example.com");
+
+    let violations = model.email_domain_violations(gh, &["rust-lang.org".to_string()]);
+    assert_eq!(
+        violations,
+        vec![("rust-lang".to_string(), "jan".to_string())]
+    );
+}
+
+#[test]
+fn org_domains_pending_verification() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let mut gh = model.gh_model();
+    gh.add_unverified_domain("rust-lang.org");
+    gh.add_unverified_domain("crates.io");
+
+    let pending = model.pending_domain_verifications(gh);
+    assert_eq!(
+        pending,
+        vec![
+            ("rust-lang".to_string(), "crates.io".to_string()),
+            ("rust-lang".to_string(), "rust-lang.org".to_string())
+        ]
+    );
+}
+
+#[test]
+fn org_domains_unreadable() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let mut gh = model.gh_model();
+    gh.add_unverified_domain("rust-lang.org");
+    gh.forbid_domains();
+
+    let pending = model.pending_domain_verifications(gh);
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn repos_missing_health_files() {
+    let mut model = DataModel::default();
+    model.create_repo(RepoData::new("repo1"));
+    model.create_repo(RepoData::new("repo2"));
+    model.create_repo(RepoData::new("repo3").archived(true));
+    let mut gh = model.gh_model();
+    gh.add_health_file(".github", "code_of_conduct");
+    gh.add_health_file("repo1", "issue_template");
+    gh.add_health_file("repo1", "pull_request_template");
+    gh.add_security_policy("repo1");
+    // Not created yet
+    model.create_repo(RepoData::new("repo4"));
+
+    let missing = model.missing_health_files(gh);
+    assert_eq!(
+        missing,
+        vec![(
+            "rust-lang".to_string(),
+            "repo2".to_string(),
+            vec!["issue_template", "pull_request_template", "security_policy"]
+        )]
+    );
+}
+
+#[test]
+fn repo_collaborators_with_excess_grants() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_user("sakura");
+    model.create_team(TeamData::new("team1").gh_team("team1-gh", &[user, user2]));
+    model.create_repo(
+        RepoData::new("repo1")
+            .team("team1-gh", RepoPermission::Write)
+            .member("mark", RepoPermission::Admin)
+            .member("jan", RepoPermission::Triage)
+            .member("sakura", RepoPermission::Maintain),
+    );
+    let gh = model.gh_model();
+
+    let grants = model.excess_collaborator_grants(gh);
+    assert_eq!(
+        grants,
+        vec![
+            (
+                "rust-lang".to_string(),
+                "repo1".to_string(),
+                "mark".to_string(),
+                api::RepoPermission::Admin,
+                api::RepoPermission::Write
+            ),
+            (
+                "rust-lang".to_string(),
+                "repo1".to_string(),
+                "jan".to_string(),
+                api::RepoPermission::Triage,
+                api::RepoPermission::Write
+            )
+        ]
+    );
+}
+
+#[test]
+fn team_leads_are_maintainers() {
+    let mut model = DataModel::default();
+    let lead = model.create_user("mark");
+    let member = model.create_user("jan");
+    model.create_team(
+        TeamData::new("admins")
+            .gh_team("admins-gh", &[lead, member])
+            .gh_team("admins-bots", &[lead])
+            .leads(vec![lead]),
+    );
+    let gh = model.gh_model();
+
+    let diff = model.diff_teams_with_lead_maintainers(gh, "rust-lang, !rust-lang/admins-bots");
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [
+                    (
+                        "jan",
+                        Noop,
+                    ),
+                    (
+                        "mark",
+                        ChangeRole(
+                            (
+                                Member,
+                                Maintainer,
+                            ),
+                        ),
+                    ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-bots",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [
+                    (
+                        "mark",
+                        Noop,
+                    ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+    ]
+    "#);
+}
+
+#[test]
+fn team_remove_denied_users() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    let user3 = model.create_user("sofia");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    model.get_team("admins").add_gh_member("admins-gh", user3);
+    model.create_team(TeamData::new("reviewers").gh_team("reviewers-gh", &[user2, user3]));
+
+    let mut diff = model.diff_all(gh);
+    diff.remove_denied_users(&["jan".to_string(), "sofia".to_string()].into());
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      Deleting member 'jan' (on the deny list)
+      Not adding member 'sofia' (on the deny list, but in the team repo)
+    ➕ Creating team:
+      Org: rust-lang
+      Name: reviewers-gh
+      Description: Managed by the rust-lang/team repository.
+      Privacy: closed
+      Members:
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn org_remove_denied_users() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_user("sofia");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    // jan is removed from the org even though no team diff mentions them
+    let removals = model.denied_org_members(gh, &["sofia", "jan"]);
+    insta::assert_debug_snapshot!(removals, @r#"
+    [
+        OrgMemberRemoval {
+            org: "rust-lang",
+            login: "jan",
+        },
+    ]
+    "#);
+}
+
+#[test]
+fn team_hold_mass_removals() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user, user2]));
+    let gh = model.gh_model();
+
+    let team = model.get_team("admins");
+    team.remove_gh_member("admins-gh", user2);
+    team.add_alumni(user2);
+
+    let mut diff = model.diff_all(gh);
+    diff.hold_mass_removals(40, &[]);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      50% of the members would be removed, held pending confirmation
+      Holding the removal of member 'jan'
+    💻 Repo Diffs:
+    "#);
+}
+
+#[test]
+fn audit_log_appends_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let audit_log = AuditLog::new(path.clone(), "abc123".to_string());
+    let entry = |operation, member, reason| AuditEntry {
+        operation,
+        org: "rust-lang",
+        team: "admins-gh",
+        member,
+        reason,
+    };
+    audit_log
+        .record(&entry("add", "mark", "member of the team in the team repo"))
+        .unwrap();
+    audit_log
+        .record(&entry(
+            "remove",
+            "jan",
+            "alumni of the team in the team repo",
+        ))
+        .unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents
+        .lines()
+        .map(|line| {
+            let mut line: serde_json::Value = serde_json::from_str(line).unwrap();
+            line.as_object_mut().unwrap().remove("applied_at").unwrap();
+            line.to_string()
+        })
+        .collect::<Vec<_>>();
+    insta::assert_snapshot!(lines.join("\n"), @r#"
+    {"member":"mark","operation":"add","org":"rust-lang","reason":"member of the team in the team repo","source_ref":"abc123","team":"admins-gh"}
+    {"member":"jan","operation":"remove","org":"rust-lang","reason":"alumni of the team in the team repo","source_ref":"abc123","team":"admins-gh"}
+    "#);
+}
+
+#[test]
+fn transaction_log_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transactions.jsonl");
+    let transaction_log = TransactionLog::new(path.clone(), "abc123".to_string());
+    let transactions = vec![
+        Transaction::CreateTeam {
+            org: "rust-lang".to_string(),
+            name: "admins-gh".to_string(),
+            description: "Managed by the rust-lang/team repository.".to_string(),
+            privacy: TeamPrivacy::Closed,
+        },
+        Transaction::SetMembership {
+            org: "rust-lang".to_string(),
+            team: "admins-gh".to_string(),
+            member: "mark".to_string(),
+            role: TeamRole::Maintainer,
+        },
+        Transaction::RemoveMembership {
+            org: "rust-lang".to_string(),
+            team: "admins-gh".to_string(),
+            member: "jan".to_string(),
+        },
+    ];
+    for transaction in &transactions {
+        transaction_log.record(transaction).unwrap();
+    }
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents
+        .lines()
+        .map(|line| {
+            let mut line: serde_json::Value = serde_json::from_str(line).unwrap();
+            line.as_object_mut().unwrap().remove("applied_at").unwrap();
+            line.to_string()
+        })
+        .collect::<Vec<_>>();
+    insta::assert_snapshot!(lines.join("\n"), @r#"
+    {"description":"Managed by the rust-lang/team repository.","name":"admins-gh","op":"create-team","org":"rust-lang","privacy":"closed","source_ref":"abc123","version":1}
+    {"member":"mark","op":"set-membership","org":"rust-lang","role":"maintainer","source_ref":"abc123","team":"admins-gh","version":1}
+    {"member":"jan","op":"remove-membership","org":"rust-lang","source_ref":"abc123","team":"admins-gh","version":1}
+    "#);
+    assert_eq!(read_transactions(&path).unwrap(), transactions);
+}
+
+#[test]
+fn team_member_with_migrated_account() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let migrated = model.create_user("user1");
+    model.create_team(
+        TeamData::new("admins")
+            .gh_team("admins-gh", &[user, migrated])
+            .leads(vec![migrated]),
+    );
+    let mut gh = model.gh_model();
+    gh.migrate_user(migrated, 5);
+
+    let (diff, migrations) = model.diff_teams_with_account_migrations(gh);
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [
+                    (
+                        "mark",
+                        Noop,
+                    ),
+                    (
+                        "user1",
+                        Noop,
+                    ),
+                ],
+                duplicate_definitions: [],
+                idp_groups: [],
+            },
+        ),
+    ]
+    "#);
+    insta::assert_debug_snapshot!(migrations, @r#"
+    [
+        AccountMigration {
+            login: "user1",
+            data_id: 1,
+            current_id: 5,
+        },
+    ]
+    "#);
+}
+
+#[test]
+fn team_synchronized_from_idp_groups() {
+    let mut model = DataModel::default();
+    let user = model.create_user("mark");
+    let user2 = model.create_user("jan");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[user]));
+    let mut gh = model.gh_model();
+    gh.add_idp_group("admins-gh", "okta-admins");
+    let mut gh2 = model.gh_model();
+    gh2.add_idp_group("admins-gh", "okta-admins");
+    model.get_team("admins").add_gh_member("admins-gh", user2);
+
+    // The IdP groups are only looked up when asked to
+    let diff = model.diff_teams(gh2);
+    insta::assert_snapshot!(diff[0].to_string(), @r#"
+    📝 Editing team 'rust-lang/admins-gh':
+      Adding member 'jan' with member role
+    "#);
+
+    let diff = model.diff_teams_with_idp_groups(gh);
+    insta::assert_debug_snapshot!(diff, @r#"
+    [
+        Edit(
+            EditTeamDiff {
+                org: "rust-lang",
+                name: "admins-gh",
+                updated_at: None,
+                name_diff: None,
+                description_diff: None,
+                privacy_diff: None,
+                member_diffs: [],
+                duplicate_definitions: [],
+                idp_groups: [
+                    "okta-admins",
+                ],
+            },
+        ),
+    ]
+    "#);
+    insta::assert_snapshot!(diff[0].to_string(), @r#"
+    📝 Editing team 'rust-lang/admins-gh':
+      Externally managed: members synchronized from the IdP groups okta-admins
+    "#);
+}
+
+#[test]
+fn repo_hold_review_count_reduction() {
+    let mut model = DataModel::default();
+    model.create_repo(
+        RepoData::new("repo1")
+            .team("team1", RepoPermission::Write)
+            .branch_protections(vec![BranchProtectionBuilder::pr_required(
+                "master",
+                &["test"],
+                2,
+            )
+            .build()]),
+    );
+
+    let (gh, gh2, gh3) = (model.gh_model(), model.gh_model(), model.gh_model());
+    model.get_repo("repo1").branch_protections[0].mode = BranchProtectionMode::PrRequired {
+        ci_checks: vec!["test".to_string()],
+        required_approvals: 1,
+    };
+
+    let diff = model.diff_all(gh);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      Branch Protections:
+          master
+            Reducing required approvals: 2 => 1 (weakening)
+    "#);
+    insta::assert_debug_snapshot!(diff.estimated_writes(), @r#"
+    {
+        "required approval reductions": 1,
+    }
+    "#);
+
+    let mut diff = model.diff_all(gh2);
+    diff.hold_review_count_reductions(&[]);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      Branch Protections:
+          master
+            Holding the reduction of required approvals: 2 => 1 (weakening)
+    "#);
+
+    let mut diff = model.diff_all(gh3);
+    diff.hold_review_count_reductions(&["rust-lang/repo1".to_string()]);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    💻 Repo Diffs:
+    📝 Editing repo 'rust-lang/repo1':
+      Branch Protections:
+          master
+            Reducing required approvals: 2 => 1 (weakening)
+    "#);
+}
+
+#[test]
+fn team_keep_token_owner() {
+    let mut model = DataModel::default();
+    let bot = model.create_user("sync-bot");
+    let user = model.create_user("mark");
+    model.create_team(TeamData::new("admins").gh_team("admins-gh", &[bot, user]));
+    let gh = model.gh_model();
+    let team = model.get_team("admins");
+    team.remove_gh_member("admins-gh", bot);
+    team.remove_gh_member("admins-gh", user);
+
+    let mut diff = model.diff_all(gh);
+    diff.keep_token_owners(&["sync-bot".to_string()]);
+    insta::assert_snapshot!(diff, @r#"
+    💻 Team Diffs:
+    📝 Editing team 'rust-lang/admins-gh':
+      The team has no members in the team repo, check the team data
+      Deleting member 'mark' (added out-of-band, not in the team repo)
+      Keeping member 'sync-bot' as is (the sync runs as them, despite the team repo)
+    💻 Repo Diffs:
+    "#);
+}
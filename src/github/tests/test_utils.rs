@@ -11,7 +11,8 @@ use crate::github::api::{
     RepoUser, Team, TeamMember, TeamPrivacy, TeamRole,
 };
 use crate::github::{
-    api, construct_branch_protection, convert_permission, RepoDiff, SyncGitHub, TeamDiff,
+    api, construct_branch_protection, convert_permission, AccountMigration, Diff, LeadMaintainers,
+    OrgMemberRemoval, RepoDiff, SyncGitHub, TeamDiff,
 };
 
 const DEFAULT_ORG: &str = "rust-lang";
@@ -65,6 +66,10 @@ impl DataModel {
             .expect("Repo not found")
     }
 
+    pub fn remove_repo(&mut self, name: &str) {
+        self.repos.retain(|r| r.name != name);
+    }
+
     /// Creates a GitHub model from the current team data mock.
     /// Note that all users should have been created before calling this method, so that
     /// GitHub knows about the users' existence.
@@ -103,6 +108,7 @@ impl DataModel {
                     description: Some("Managed by the rust-lang/team repository.".to_string()),
                     privacy: TeamPrivacy::Closed,
                     slug: gh_team.name.clone(),
+                    updated_at: None,
                 })
             }
         }
@@ -123,6 +129,7 @@ impl DataModel {
                     homepage: repo.homepage.clone(),
                     archived: false,
                     allow_auto_merge: None,
+                    updated_at: None,
                 },
             );
             let teams = repo
@@ -164,12 +171,21 @@ impl DataModel {
         GithubMock {
             users,
             owners: Default::default(),
+            verified_emails: Default::default(),
+            unverified_domains: Default::default(),
+            domains_forbidden: false,
             teams,
             team_memberships,
             team_invitations: Default::default(),
+            org_invitations: Default::default(),
+            team_parents: Default::default(),
             repos,
             repo_members,
             branch_protections,
+            health_files: Default::default(),
+            security_policies: Default::default(),
+            repo_invitations: Default::default(),
+            idp_groups: Default::default(),
         }
     }
 
@@ -179,17 +195,101 @@ impl DataModel {
             .expect("Cannot diff teams")
     }
 
+    pub fn diff_teams_with_account_migrations(
+        &self,
+        github: GithubMock,
+    ) -> (Vec<TeamDiff>, Vec<AccountMigration>) {
+        let sync = self.create_sync(github);
+        let diff = sync.diff_teams().expect("Cannot diff teams");
+        (diff, sync.account_migrations)
+    }
+
+    pub fn diff_teams_with_lead_maintainers(
+        &self,
+        github: GithubMock,
+        lead_maintainers: &str,
+    ) -> Vec<TeamDiff> {
+        let mut sync = self.create_sync(github);
+        sync.lead_maintainers = LeadMaintainers::parse(lead_maintainers);
+        sync.diff_teams().expect("Cannot diff teams")
+    }
+
+    pub fn diff_teams_with_idp_groups(&self, github: GithubMock) -> Vec<TeamDiff> {
+        let mut sync = self.create_sync(github);
+        sync.check_idp_groups = true;
+        sync.diff_teams().expect("Cannot diff teams")
+    }
+
     pub fn diff_repos(&self, github: GithubMock) -> Vec<RepoDiff> {
         self.create_sync(github)
             .diff_repos()
             .expect("Cannot diff repos")
     }
 
+    pub fn diff_repos_concurrently(&self, github: GithubMock, concurrency: usize) -> Vec<RepoDiff> {
+        let mut sync = self.create_sync(github);
+        sync.read_concurrency = concurrency;
+        sync.diff_repos().expect("Cannot diff repos")
+    }
+
+    pub fn diff_unmanaged_repos(&self, github: GithubMock) -> Vec<RepoDiff> {
+        self.create_sync(github)
+            .diff_unmanaged_repos()
+            .expect("Cannot diff unmanaged repos")
+    }
+
+    pub fn diff_all(&self, github: GithubMock) -> Diff {
+        self.create_sync(github)
+            .diff_all()
+            .expect("Cannot diff teams and repos")
+    }
+
+    pub fn denied_org_members(&self, github: GithubMock, denied: &[&str]) -> Vec<OrgMemberRemoval> {
+        let denied = denied.iter().map(|login| login.to_string()).collect();
+        self.create_sync(github)
+            .denied_org_members(&denied)
+            .expect("Cannot look up denied org members")
+    }
+
+    pub fn email_domain_violations(
+        &self,
+        github: GithubMock,
+        allowed: &[String],
+    ) -> Vec<(String, String)> {
+        self.create_sync(github)
+            .email_domain_violations(allowed)
+            .expect("Cannot check email domains")
+    }
+
+    pub fn pending_domain_verifications(&self, github: GithubMock) -> Vec<(String, String)> {
+        self.create_sync(github).pending_domain_verifications()
+    }
+
+    pub fn missing_health_files(&self, github: GithubMock) -> Vec<(String, String, Vec<&str>)> {
+        let sync = self.create_sync(github);
+        let repo_diffs = sync.diff_repos().expect("Cannot diff repos");
+        sync.missing_health_files(&repo_diffs)
+            .expect("Cannot check health files")
+    }
+
+    pub fn excess_collaborator_grants(
+        &self,
+        github: GithubMock,
+    ) -> Vec<(
+        String,
+        String,
+        String,
+        api::RepoPermission,
+        api::RepoPermission,
+    )> {
+        self.create_sync(github).excess_collaborator_grants()
+    }
+
     fn create_sync(&self, github: GithubMock) -> SyncGitHub {
         let teams = self.teams.iter().cloned().map(|t| t.into()).collect();
         let repos = self.repos.iter().cloned().map(|r| r.into()).collect();
 
-        SyncGitHub::new(Box::new(github), teams, repos).expect("Cannot create SyncGitHub")
+        SyncGitHub::new(Box::new(github), teams, repos, None).expect("Cannot create SyncGitHub")
     }
 }
 
@@ -201,6 +301,10 @@ pub struct TeamData {
     name: String,
     #[builder(default)]
     gh_teams: Vec<GitHubTeam>,
+    #[builder(default)]
+    alumni: Vec<UserId>,
+    #[builder(default)]
+    leads: Vec<UserId>,
 }
 
 impl TeamData {
@@ -216,6 +320,10 @@ impl TeamData {
         self.github_team(team).members.retain(|u| *u != user);
     }
 
+    pub fn add_alumni(&mut self, user: UserId) {
+        self.alumni.push(user);
+    }
+
     pub fn remove_gh_team(&mut self, name: &str) {
         self.gh_teams.retain(|t| t.name != name);
     }
@@ -234,14 +342,34 @@ impl From<TeamData> for v1::Team {
             name,
             kind,
             gh_teams,
+            alumni,
+            leads,
         } = value;
         v1::Team {
             name: name.clone(),
             kind,
             subteam_of: None,
             top_level: None,
-            members: vec![],
-            alumni: vec![],
+            members: leads
+                .into_iter()
+                .map(|github_id| v1::TeamMember {
+                    name: format!("user{github_id}"),
+                    github: format!("user{github_id}"),
+                    github_id,
+                    is_lead: true,
+                    roles: vec![],
+                })
+                .collect(),
+            alumni: alumni
+                .into_iter()
+                .map(|github_id| v1::TeamMember {
+                    name: format!("user{github_id}"),
+                    github: format!("user{github_id}"),
+                    github_id,
+                    is_lead: false,
+                    roles: vec![],
+                })
+                .collect(),
             github: (!gh_teams.is_empty()).then_some(TeamGitHub { teams: gh_teams }),
             website_data: None,
             roles: vec![],
@@ -415,17 +543,35 @@ pub struct GithubMock {
     users: HashMap<UserId, String>,
     // org name -> user ID
     owners: HashMap<String, Vec<UserId>>,
+    // login -> emails in the domains verified by the org
+    verified_emails: HashMap<String, Vec<String>>,
+    // Domains of the org pending verification
+    unverified_domains: Vec<String>,
+    // Whether reading the domains of the org is refused, as it is without an owner's token
+    domains_forbidden: bool,
     teams: Vec<Team>,
     // Team name -> members
     team_memberships: HashMap<String, HashMap<UserId, TeamMember>>,
     // Team name -> list of invited users
     team_invitations: HashMap<String, Vec<String>>,
+    // Users invited to join the org
+    org_invitations: Vec<String>,
+    // Team name -> name of its parent team
+    team_parents: HashMap<String, String>,
     // Repo name -> repo data
     repos: HashMap<String, Repo>,
     // Repo name -> (teams, members)
     repo_members: HashMap<String, RepoMembers>,
     // Repo name -> Vec<(protection ID, branch protection)>
     branch_protections: HashMap<String, Vec<(String, BranchProtection)>>,
+    // Repo name -> community health files
+    health_files: HashMap<String, HashSet<String>>,
+    // Names of the repos with a security policy
+    security_policies: HashSet<String>,
+    // Repo name -> users invited to collaborate
+    repo_invitations: HashMap<String, Vec<RepoUser>>,
+    // Team name -> IdP groups its membership is synchronized from
+    idp_groups: HashMap<String, Vec<String>>,
 }
 
 impl GithubMock {
@@ -435,6 +581,77 @@ impl GithubMock {
             .or_default()
             .push(user.to_string());
     }
+
+    pub fn set_team_parent(&mut self, team: &str, parent: &str) {
+        self.team_parents
+            .insert(team.to_string(), parent.to_string());
+    }
+
+    pub fn add_org_invitation(&mut self, user: &str) {
+        self.org_invitations.push(user.to_string());
+    }
+
+    /// Gives a user another id, like GitHub does when an account is migrated
+    pub fn migrate_user(&mut self, id: UserId, new_id: UserId) {
+        let login = self.users.remove(&id).expect("User not found");
+        self.users.insert(new_id, login);
+        for members in self.team_memberships.values_mut() {
+            if let Some(member) = members.remove(&id) {
+                members.insert(new_id, member);
+            }
+        }
+    }
+
+    pub fn add_verified_email(&mut self, user: &str, email: &str) {
+        self.verified_emails
+            .entry(user.to_string())
+            .or_default()
+            .push(email.to_string());
+    }
+
+    pub fn add_unverified_domain(&mut self, domain: &str) {
+        self.unverified_domains.push(domain.to_string());
+    }
+
+    pub fn forbid_domains(&mut self) {
+        self.domains_forbidden = true;
+    }
+
+    pub fn add_health_file(&mut self, repo: &str, file: &str) {
+        self.health_files
+            .entry(repo.to_string())
+            .or_default()
+            .insert(file.to_string());
+    }
+
+    pub fn add_repo_invitation(&mut self, repo: &str, user: &str, permission: RepoPermission) {
+        self.repo_invitations
+            .entry(repo.to_string())
+            .or_default()
+            .push(RepoUser {
+                name: user.to_string(),
+                permission: convert_permission(&permission),
+            });
+    }
+
+    pub fn add_security_policy(&mut self, repo: &str) {
+        self.security_policies.insert(repo.to_string());
+    }
+
+    pub fn add_idp_group(&mut self, team: &str, group: &str) {
+        self.idp_groups
+            .entry(team.to_string())
+            .or_default()
+            .push(group.to_string());
+    }
+
+    pub fn set_team_privacy(&mut self, team: &str, privacy: TeamPrivacy) {
+        self.teams
+            .iter_mut()
+            .find(|t| t.name == team)
+            .expect("Team not found")
+            .privacy = privacy;
+    }
 }
 
 impl GithubRead for GithubMock {
@@ -447,6 +664,14 @@ impl GithubRead for GithubMock {
             .collect())
     }
 
+    fn user_id(&self, login: &str) -> anyhow::Result<Option<UserId>> {
+        Ok(self
+            .users
+            .iter()
+            .find(|(_, user)| *user == login)
+            .map(|(id, _)| *id))
+    }
+
     fn org_owners(&self, org: &str) -> anyhow::Result<HashSet<UserId>> {
         Ok(self
             .owners
@@ -457,6 +682,29 @@ impl GithubRead for GithubMock {
             .collect())
     }
 
+    fn org_members_verified_emails(
+        &self,
+        org: &str,
+    ) -> anyhow::Result<HashMap<String, Vec<String>>> {
+        assert_eq!(org, DEFAULT_ORG);
+        Ok(self
+            .users
+            .values()
+            .map(|user| {
+                let emails = self.verified_emails.get(user).cloned();
+                (user.clone(), emails.unwrap_or_default())
+            })
+            .collect())
+    }
+
+    fn org_unverified_domains(&self, org: &str) -> anyhow::Result<Vec<String>> {
+        assert_eq!(org, DEFAULT_ORG);
+        if self.domains_forbidden {
+            anyhow::bail!("403 Forbidden: Must be an organization owner");
+        }
+        Ok(self.unverified_domains.clone())
+    }
+
     fn org_app_installations(&self, _org: &str) -> anyhow::Result<Vec<OrgAppInstallation>> {
         Ok(vec![])
     }
@@ -468,12 +716,15 @@ impl GithubRead for GithubMock {
         Ok(vec![])
     }
 
-    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String)>> {
+    fn org_teams(&self, org: &str) -> anyhow::Result<Vec<(String, String, Option<String>)>> {
         assert_eq!(org, DEFAULT_ORG);
         Ok(self
             .teams
             .iter()
-            .map(|team| (team.name.clone(), team.slug.clone()))
+            .map(|team| {
+                let parent = self.team_parents.get(&team.name).cloned();
+                (team.name.clone(), team.slug.clone(), parent)
+            })
             .collect())
     }
 
@@ -494,7 +745,7 @@ impl GithubRead for GithubMock {
         &self,
         org: &str,
         team: &str,
-    ) -> anyhow::Result<HashSet<String>> {
+    ) -> anyhow::Result<HashMap<String, Option<String>>> {
         assert_eq!(org, DEFAULT_ORG);
         Ok(self
             .team_invitations
@@ -502,9 +753,36 @@ impl GithubRead for GithubMock {
             .cloned()
             .unwrap_or_default()
             .into_iter()
+            .map(|user| (user, None))
             .collect())
     }
 
+    fn org_membership(&self, org: &str, login: &str) -> anyhow::Result<bool> {
+        assert_eq!(org, DEFAULT_ORG);
+        // The members of the org are the members of its teams
+        Ok(self
+            .team_memberships
+            .values()
+            .flat_map(|members| members.values())
+            .any(|member| member.username == login))
+    }
+
+    fn org_invitations(&self, org: &str) -> anyhow::Result<HashMap<String, Option<String>>> {
+        assert_eq!(org, DEFAULT_ORG);
+        Ok(self
+            .org_invitations
+            .iter()
+            .map(|user| (user.clone(), None))
+            .collect())
+    }
+
+    fn org_repos(&self, org: &str) -> anyhow::Result<Vec<Repo>> {
+        assert_eq!(org, DEFAULT_ORG);
+        let mut repos = self.repos.values().cloned().collect::<Vec<_>>();
+        repos.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(repos)
+    }
+
     fn repo(&self, org: &str, repo: &str) -> anyhow::Result<Option<Repo>> {
         assert_eq!(org, DEFAULT_ORG);
         Ok(self.repos.get(repo).cloned())
@@ -531,6 +809,11 @@ impl GithubRead for GithubMock {
             .unwrap_or_default())
     }
 
+    fn repo_invitations(&self, org: &str, repo: &str) -> anyhow::Result<Vec<RepoUser>> {
+        assert_eq!(org, DEFAULT_ORG);
+        Ok(self.repo_invitations.get(repo).cloned().unwrap_or_default())
+    }
+
     fn branch_protections(
         &self,
         org: &str,
@@ -548,6 +831,26 @@ impl GithubRead for GithubMock {
 
         Ok(result)
     }
+
+    fn repo_health_files(&self, org: &str, repo: &str) -> anyhow::Result<Option<HashSet<String>>> {
+        assert_eq!(org, DEFAULT_ORG);
+        let files = self.health_files.get(repo).cloned();
+        Ok(files.or_else(|| self.repos.contains_key(repo).then(HashSet::new)))
+    }
+
+    fn repo_has_security_policy(&self, org: &str, repo: &str) -> anyhow::Result<bool> {
+        assert_eq!(org, DEFAULT_ORG);
+        Ok(self.security_policies.contains(repo))
+    }
+
+    fn team_idp_groups(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        assert_eq!(org, DEFAULT_ORG);
+        Ok(self.idp_groups.get(team).cloned().unwrap_or_default())
+    }
+
+    fn token_owners(&self) -> anyhow::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 #[derive(Clone)]
@@ -0,0 +1,171 @@
+use crate::github::api::{GitHubWrite, TeamPrivacy, TeamRole};
+use anyhow::Context;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version of the format of the [`TransactionLog`] lines, to be bumped on incompatible changes
+const VERSION: u32 = 1;
+
+/// An append-only file recording every write applied to GitHub teams, one JSON object per line.
+/// Unlike the [`AuditLog`](super::AuditLog), the lines carry everything needed to apply the writes
+/// again, so that replaying the accumulated logs in order on an org recreates its managed teams.
+pub(crate) struct TransactionLog {
+    path: PathBuf,
+    /// The commit of the team repo the writes come from
+    source_ref: String,
+}
+
+/// A write to GitHub teams, as recorded in the [`TransactionLog`]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub(crate) enum Transaction {
+    CreateTeam {
+        org: String,
+        name: String,
+        description: String,
+        privacy: TeamPrivacy,
+    },
+    EditTeam {
+        org: String,
+        name: String,
+        new_name: Option<String>,
+        new_description: Option<String>,
+        new_privacy: Option<TeamPrivacy>,
+    },
+    DetachTeam {
+        org: String,
+        slug: String,
+    },
+    DeleteTeam {
+        org: String,
+        slug: String,
+    },
+    SetMembership {
+        org: String,
+        team: String,
+        member: String,
+        role: TeamRole,
+    },
+    RemoveMembership {
+        org: String,
+        team: String,
+        member: String,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct Line<'a> {
+    version: u32,
+    /// Seconds since the Unix epoch
+    applied_at: u64,
+    source_ref: &'a str,
+    #[serde(flatten)]
+    transaction: &'a Transaction,
+}
+
+impl TransactionLog {
+    pub(crate) fn new(path: PathBuf, source_ref: String) -> Self {
+        Self { path, source_ref }
+    }
+
+    pub(crate) fn record(&self, transaction: &Transaction) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(&Line {
+            version: VERSION,
+            applied_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            source_ref: &self.source_ref,
+            transaction,
+        })?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| {
+                format!(
+                    "failed to open the transaction log at {}",
+                    self.path.display()
+                )
+            })?;
+        // One write per line, so that the lines of concurrently applied writes don't interleave
+        line.push('\n');
+        file.write_all(line.as_bytes()).with_context(|| {
+            format!(
+                "failed to write the transaction log at {}",
+                self.path.display()
+            )
+        })
+    }
+}
+
+/// Reads the transactions of a [`TransactionLog`], in the order they were applied
+pub(crate) fn read_transactions(path: &Path) -> anyhow::Result<Vec<Transaction>> {
+    #[derive(serde::Deserialize)]
+    struct Line {
+        version: u32,
+        #[serde(flatten)]
+        transaction: Transaction,
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read the transaction log at {}", path.display()))?;
+    let mut transactions = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: Line = serde_json::from_str(line)
+            .with_context(|| format!("invalid transaction at {}:{}", path.display(), i + 1))?;
+        if line.version != VERSION {
+            anyhow::bail!(
+                "unsupported transaction version {} at {}:{}",
+                line.version,
+                path.display(),
+                i + 1
+            );
+        }
+        transactions.push(line.transaction);
+    }
+    Ok(transactions)
+}
+
+/// Applies the transactions again, in order. They are meant to be replayed on an org without the
+/// managed teams, as creating a team that already exists fails.
+pub(crate) fn replay(transactions: Vec<Transaction>, sync: &GitHubWrite) -> anyhow::Result<()> {
+    for transaction in transactions {
+        match transaction {
+            Transaction::CreateTeam {
+                org,
+                name,
+                description,
+                privacy,
+            } => {
+                sync.create_team(&org, &name, &description, privacy)?;
+            }
+            Transaction::EditTeam {
+                org,
+                name,
+                new_name,
+                new_description,
+                new_privacy,
+            } => sync.edit_team(
+                &org,
+                &name,
+                new_name.as_deref(),
+                new_description.as_deref(),
+                new_privacy,
+            )?,
+            Transaction::DetachTeam { org, slug } => sync.detach_team(&org, &slug)?,
+            Transaction::DeleteTeam { org, slug } => sync.delete_team(&org, &slug)?,
+            Transaction::SetMembership {
+                org,
+                team,
+                member,
+                role,
+            } => sync.set_team_membership(&org, &team, &member, role)?,
+            Transaction::RemoveMembership { org, team, member } => {
+                sync.remove_team_membership(&org, &team, &member)?
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use crate::github::api::GithubRead;
+use anyhow::Context;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a resolved username is trusted before it's resolved again, to pick up renames.
+const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    login: String,
+    /// Seconds since the Unix epoch
+    resolved_at: u64,
+}
+
+/// Resolve user ids to usernames, reusing the logins stored in the cache file at `path` by
+/// previous runs and only asking GitHub about ids that are missing or expired.
+///
+/// The cache file is updated with the newly resolved usernames.
+pub(super) fn resolve_cached(
+    github: &dyn GithubRead,
+    ids: &[u64],
+    path: &Path,
+) -> anyhow::Result<HashMap<u64, String>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut cache: HashMap<u64, CacheEntry> = match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            warn!(
+                "ignoring the corrupted username cache at {}: {err}",
+                path.display()
+            );
+            HashMap::new()
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("failed to read the username cache at {}", path.display())
+            })
+        }
+    };
+    cache.retain(|_, entry| now.saturating_sub(entry.resolved_at) < CACHE_TTL.as_secs());
+
+    let missing = ids
+        .iter()
+        .filter(|id| !cache.contains_key(id))
+        .copied()
+        .collect::<Vec<_>>();
+    debug!(
+        "{} of {} usernames found in the cache at {}",
+        ids.len() - missing.len(),
+        ids.len(),
+        path.display()
+    );
+    if !missing.is_empty() {
+        for (id, login) in github.usernames(&missing)? {
+            cache.insert(
+                id,
+                CacheEntry {
+                    login,
+                    resolved_at: now,
+                },
+            );
+        }
+    }
+
+    std::fs::write(path, serde_json::to_vec(&cache)?)
+        .with_context(|| format!("failed to write the username cache at {}", path.display()))?;
+
+    Ok(ids
+        .iter()
+        .filter_map(|id| cache.get(id).map(|entry| (*id, entry.login.clone())))
+        .collect())
+}
@@ -14,19 +14,43 @@ const DESCRIPTION: &str = "managed by an automatic script on github";
 // Limit (in bytes) of the size of a Mailgun rule's actions list.
 const ACTIONS_SIZE_LIMIT_BYTES: usize = 4000;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
 struct List {
     address: String,
+    /// Only a hash of the members is serialized, as they might be decrypted emails. It still lets
+    /// a saved plan tell apart lists whose members changed since.
+    #[serde(rename = "members_hash", serialize_with = "serialize_members_hash")]
     members: Vec<String>,
     priority: i32,
 }
 
+fn serialize_members_hash<S: serde::Serializer>(
+    members: &[String],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016x}", members_hash(members)))
+}
+
+/// A 64-bit FNV-1a hash of the sorted members. Unlike the standard library hashers, its value
+/// doesn't depend on the Rust release, so plans saved by different builds can be compared.
+fn members_hash(members: &[String]) -> u64 {
+    let mut members = members.iter().collect::<Vec<_>>();
+    members.sort();
+    let mut hash = 0xcbf29ce484222325_u64;
+    for member in members {
+        for byte in member.bytes().chain(std::iter::once(b'\n')) {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
 fn mangle_lists(email_encryption_key: &str, lists: team_data::Lists) -> anyhow::Result<Vec<List>> {
     let mut result = Vec::new();
 
     for (_key, mut list) in lists.lists.into_iter() {
         // Handle encrypted list addresses.
-        list.address = email_encryption::try_decrypt(email_encryption_key, &list.address)?;
+        list.address = decrypt(email_encryption_key, &list.address)?;
 
         let base_list = List {
             address: mangle_address(&list.address)?,
@@ -50,7 +74,7 @@ fn mangle_lists(email_encryption_key: &str, lists: team_data::Lists) -> anyhow::
         let mut partitions_count = 0;
         for mut member in list.members {
             // Handle encrypted member email addresses.
-            member = email_encryption::try_decrypt(email_encryption_key, &member)?;
+            member = decrypt(email_encryption_key, &member)?;
 
             let action = build_route_action(&member);
             if current_actions_len + action.len() > ACTIONS_SIZE_LIMIT_BYTES {
@@ -72,6 +96,12 @@ fn mangle_lists(email_encryption_key: &str, lists: team_data::Lists) -> anyhow::
     Ok(result)
 }
 
+/// Decrypts an email address if it's encrypted.
+fn decrypt(email_encryption_key: &str, email: &str) -> anyhow::Result<String> {
+    email_encryption::try_decrypt(email_encryption_key, email)
+        .with_context(|| format!("failed to decrypt `{email}`, is EMAIL_ENCRYPTION_KEY correct?"))
+}
+
 fn mangle_address(addr: &str) -> anyhow::Result<String> {
     // Escape dots since they have a special meaning in Python regexes
     let mangled = addr.replace('.', "\\.");
@@ -86,64 +116,177 @@ fn mangle_address(addr: &str) -> anyhow::Result<String> {
     }
 }
 
-pub(crate) fn run(
-    token: &str,
-    email_encryption_key: &str,
-    team_api: &TeamApi,
-    dry_run: bool,
-) -> anyhow::Result<()> {
-    let mailgun = Mailgun::new(token, dry_run);
-    let mailmap = team_api.get_lists()?;
-
-    // Mangle all the mailing lists
-    let lists = mangle_lists(email_encryption_key, mailmap)?;
-
-    let mut routes = Vec::new();
-    let mut response = mailgun.get_routes(None)?;
-    let mut cur = 0u64;
-    while !response.items.is_empty() {
-        cur += response.items.len() as u64;
-        routes.extend(response.items);
-        if cur >= response.total_count {
-            break;
-        }
-        response = mailgun.get_routes(Some(cur))?;
+pub(crate) struct SyncMailgun {
+    mailgun: Mailgun,
+    lists: Vec<List>,
+}
+
+impl SyncMailgun {
+    pub(crate) fn new(
+        token: &str,
+        email_encryption_key: &str,
+        team_api: &TeamApi,
+        dry_run: bool,
+    ) -> anyhow::Result<Self> {
+        let mailgun = Mailgun::new(token, dry_run);
+        let mailmap = team_api.get_lists()?;
+
+        // Mangle all the mailing lists. This decrypts all the encrypted addresses, so a wrong
+        // encryption key is caught before any mailing list is modified.
+        let lists = mangle_lists(email_encryption_key, mailmap)?;
+
+        Ok(Self { mailgun, lists })
     }
 
-    let mut addr2list = HashMap::new();
-    for list in &lists {
-        if addr2list
-            .insert((list.address.clone(), list.priority), list)
-            .is_some()
-        {
-            bail!(
-                "duplicate address: {} (with priority {})",
-                list.address,
-                list.priority
-            );
+    pub(crate) fn diff_all(&self) -> anyhow::Result<Diff> {
+        let mut routes = Vec::new();
+        let mut response = self.mailgun.get_routes(None)?;
+        let mut cur = 0u64;
+        while !response.items.is_empty() {
+            cur += response.items.len() as u64;
+            routes.extend(response.items);
+            if cur >= response.total_count {
+                break;
+            }
+            response = self.mailgun.get_routes(Some(cur))?;
+        }
+
+        let mut addr2list = HashMap::new();
+        for list in &self.lists {
+            if addr2list
+                .insert((list.address.clone(), list.priority), list)
+                .is_some()
+            {
+                bail!(
+                    "duplicate address: {} (with priority {})",
+                    list.address,
+                    list.priority
+                );
+            }
+        }
+
+        let mut list_diffs = Vec::new();
+        for route in routes {
+            if route.description != DESCRIPTION {
+                continue;
+            }
+            let address = extract(&route.expression, "match_recipient(\"", "\")");
+            let key = (address.to_string(), route.priority);
+            match addr2list.remove(&key) {
+                Some(new_list) => list_diffs.extend(diff_list(&route, new_list)),
+                None => list_diffs.push(ListDiff::Delete {
+                    route_id: route.id.clone(),
+                    address: address.to_string(),
+                }),
+            }
+        }
+
+        for (_, list) in addr2list {
+            list_diffs.push(ListDiff::Create(list.clone()));
         }
+
+        Ok(Diff { list_diffs })
     }
+}
 
-    for route in routes {
-        if route.description != DESCRIPTION {
-            continue;
+fn diff_list(route: &api::Route, list: &List) -> Option<ListDiff> {
+    let before = route
+        .actions
+        .iter()
+        .map(|action| extract(action, "forward(\"", "\")"))
+        .collect::<HashSet<_>>();
+    let after = list.members.iter().map(|s| &s[..]).collect::<HashSet<_>>();
+    if before == after {
+        return None;
+    }
+
+    Some(ListDiff::Update {
+        route_id: route.id.clone(),
+        added: after.difference(&before).count(),
+        removed: before.difference(&after).count(),
+        list: list.clone(),
+    })
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct Diff {
+    list_diffs: Vec<ListDiff>,
+}
+
+impl Diff {
+    pub(crate) fn apply(&self, sync: &SyncMailgun) -> anyhow::Result<()> {
+        for list_diff in &self.list_diffs {
+            list_diff.apply(sync)?;
         }
-        let address = extract(&route.expression, "match_recipient(\"", "\")");
-        let key = (address.to_string(), route.priority);
-        match addr2list.remove(&key) {
-            Some(new_list) => sync(&mailgun, &route, new_list)
-                .with_context(|| format!("failed to sync {address}"))?,
-            None => mailgun
-                .delete_route(&route.id)
-                .with_context(|| format!("failed to delete {address}"))?,
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "💻 List Diffs:")?;
+        for list_diff in &self.list_diffs {
+            write!(f, "{list_diff}")?;
         }
+        Ok(())
     }
+}
 
-    for (_, list) in addr2list.iter() {
-        create(&mailgun, list).with_context(|| format!("failed to create {}", list.address))?;
+/// Member addresses might come from encrypted emails, so only the list addresses and the amount
+/// of changed members are shown in the plan.
+#[derive(serde::Serialize)]
+enum ListDiff {
+    Create(List),
+    Update {
+        route_id: String,
+        list: List,
+        added: usize,
+        removed: usize,
+    },
+    Delete {
+        route_id: String,
+        address: String,
+    },
+}
+
+impl ListDiff {
+    fn apply(&self, sync: &SyncMailgun) -> anyhow::Result<()> {
+        match self {
+            ListDiff::Create(list) => create(&sync.mailgun, list)
+                .with_context(|| format!("failed to create {}", list.address)),
+            ListDiff::Update { route_id, list, .. } => update(&sync.mailgun, route_id, list)
+                .with_context(|| format!("failed to sync {}", list.address)),
+            ListDiff::Delete { route_id, address } => sync
+                .mailgun
+                .delete_route(route_id)
+                .with_context(|| format!("failed to delete {address}")),
+        }
     }
+}
 
-    Ok(())
+impl std::fmt::Display for ListDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListDiff::Create(list) => writeln!(
+                f,
+                "➕ Creating list {} (priority {}) with {} members",
+                list.address,
+                list.priority,
+                list.members.len()
+            ),
+            ListDiff::Update {
+                list,
+                added,
+                removed,
+                ..
+            } => writeln!(
+                f,
+                "📝 Updating list {} (priority {}): {added} members added, {removed} removed",
+                list.address, list.priority
+            ),
+            ListDiff::Delete { address, .. } => writeln!(f, "❌ Deleting list {address}"),
+        }
+    }
 }
 
 fn build_route_action(member: &str) -> String {
@@ -163,20 +306,10 @@ fn create(mailgun: &Mailgun, list: &List) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn sync(mailgun: &Mailgun, route: &api::Route, list: &List) -> anyhow::Result<()> {
-    let before = route
-        .actions
-        .iter()
-        .map(|action| extract(action, "forward(\"", "\")"))
-        .collect::<HashSet<_>>();
-    let after = list.members.iter().map(|s| &s[..]).collect::<HashSet<_>>();
-    if before == after {
-        return Ok(());
-    }
-
+fn update(mailgun: &Mailgun, route_id: &str, list: &List) -> anyhow::Result<()> {
     info!("updating list {}", list.address);
     let actions = build_route_actions(list).collect::<Vec<_>>();
-    mailgun.update_route(&route.id, list.priority, &actions)?;
+    mailgun.update_route(route_id, list.priority, &actions)?;
     Ok(())
 }
 
@@ -212,6 +345,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_list_serialization_hides_members() {
+        let list = List {
+            address: "list@example.com".into(),
+            members: vec!["secret-member@example.com".into()],
+            priority: 0,
+        };
+        let json = serde_json::to_string(&ListDiff::Create(list.clone())).unwrap();
+        assert!(!json.contains("secret-member"));
+
+        // The hash is the same for every build and every order of the members
+        assert_eq!(
+            serde_json::to_value(&list).unwrap()["members_hash"],
+            "4d13ea6e4d44f737"
+        );
+        let mut changed = list.clone();
+        changed.members.push("foo@example.com".into());
+        let mut reordered = changed.clone();
+        reordered.members.reverse();
+        assert_eq!(
+            serde_json::to_value(&changed).unwrap(),
+            serde_json::to_value(&reordered).unwrap()
+        );
+        assert_ne!(
+            serde_json::to_value(&list).unwrap(),
+            serde_json::to_value(&changed).unwrap()
+        );
+    }
+
     #[test]
     fn test_mangle_address() {
         assert_eq!(
@@ -295,4 +457,24 @@ mod tests {
         ];
         assert_eq!(expected, mangled);
     }
+
+    #[test]
+    fn test_mangle_lists_wrong_key() {
+        const ENCRYPTION_KEY: &str = "mGDTk1eIx8P2gTerzKXwvun67d41iUid";
+        const WRONG_KEY: &str = "7SSrIr8WPwYJ3IMrbFFrbHHKqS7a1hYK";
+
+        let secret_member = email_encryption::encrypt(ENCRYPTION_KEY, "secret-member@example.com")
+            .expect("failed to encrypt member");
+        let lists = rust_team_data::v1::Lists {
+            lists: indexmap::indexmap![
+                "list@example.com".to_string() => rust_team_data::v1::List {
+                    address: "list@example.com".into(),
+                    members: vec!["foo@example.com".into(), secret_member],
+                },
+            ],
+        };
+
+        let err = mangle_lists(WRONG_KEY, lists).unwrap_err();
+        assert!(err.to_string().contains("is EMAIL_ENCRYPTION_KEY correct?"));
+    }
 }
@@ -1,57 +1,183 @@
+mod freeze;
 mod github;
 mod mailgun;
+mod report;
+mod service;
 mod team_api;
 mod utils;
 mod zulip;
 
-use crate::github::{create_diff, GitHubApiRead, GitHubWrite, HttpClient};
+use crate::service::Options;
 use crate::team_api::TeamApi;
-use crate::zulip::SyncZulip;
 use anyhow::Context;
 use log::{error, info, warn};
+use std::io::Write;
 
-const AVAILABLE_SERVICES: &[&str] = &["github", "mailgun", "zulip"];
 const USER_AGENT: &str = "rust-lang teams sync (https://github.com/rust-lang/sync-team)";
 
 fn usage() {
     eprintln!("available services:");
-    for service in AVAILABLE_SERVICES {
-        eprintln!("  {service}");
+    for service in service::registry() {
+        eprintln!("  {}", service.name());
     }
     eprintln!("available flags:");
-    eprintln!("  --help              Show this help message");
-    eprintln!("  --live              Apply the proposed changes to the services");
-    eprintln!("  --team-repo <path>  Path to the local team repo to use");
-    eprintln!("  --only-print-plan   Print the execution plan without executing it");
+    eprintln!("  --help                  Show this help message");
+    eprintln!("  --live                  Apply the proposed changes to the services");
+    eprintln!("  --dry-run <service>     Synchronize the service in dry mode, even with --live");
+    eprintln!("  --exclude <service>     Don't synchronize the service when defaulting to all");
+    eprintln!("  --team-repo <path>      Path to the local team repo to use");
+    eprintln!("  --only-print-plan       Print the execution plan without executing it");
+    eprintln!("  --plan-output <path>    Save the execution plan as JSON");
+    eprintln!("  --apply-plan <path>     Only apply the changes if they match a saved plan");
+    eprintln!("  --group-by <group>      Group the printed plan by `service` (default) or `org`");
+    eprintln!("  --plan-format <format>  Print the plan as `text` (default) or `json` on stdout");
+    eprintln!("  --report-html <path>    Save the plan as a readable HTML page, for reviewers");
+    eprintln!("  --fail-on-unmanaged     Fail if GitHub has entities not present in the team repo");
+    eprintln!("  --github-step-summary   Also write the plan to $GITHUB_STEP_SUMMARY");
+    eprintln!("  --report-per-user       Report per user the orgs their team changes applied in");
+    eprintln!("  --prune-repo-grants     Remove managed teams from repos not in the team repo");
+    eprintln!("  --hold-out-of-band      Keep GitHub team members added outside of the team repo");
+    eprintln!("  --check-health-files    Report repos lacking templates, a CoC or security policy");
+    eprintln!("  --check-collaborators   Report direct repo grants to members of the repo's teams");
+    eprintln!("  --idp-groups            Leave alone GitHub teams synchronized from IdP groups");
+    eprintln!("  --max-removals <pct>    Hold removing >pct% of a team/group, and approval cuts");
+    eprintln!("  --allow-removals <name> Don't hold removals of org/team, org/repo or Zulip group");
+    eprintln!("  --interactive           Ask before applying each GitHub team and repo change");
+    eprintln!("  --audit-log <path>      Append the applied GitHub membership changes to a file");
+    eprintln!("  --replay-log <path>     Append the applied GitHub team writes, for --replay");
+    eprintln!("  --replay <path>         Apply the GitHub team writes of a --replay-log again");
+    eprintln!("  --explain-api           Log each GitHub write with what GitHub returned for it");
+    eprintln!("  --ignore-freeze         Apply the changes even during a SYNC_FREEZE window");
     eprintln!("environment variables:");
-    eprintln!("  GITHUB_TOKEN          Authentication token with GitHub");
-    eprintln!("  MAILGUN_API_TOKEN     Authentication token with Mailgun");
-    eprintln!("  EMAIL_ENCRYPTION_KEY  Key used to decrypt encrypted emails in the team repo");
-    eprintln!("  ZULIP_USERNAME        Username of the Zulip bot");
-    eprintln!("  ZULIP_API_TOKEN       Autnentication token of the Zulip bot");
+    eprintln!("  GITHUB_TOKEN             Authentication token with GitHub");
+    eprintln!("  MAILGUN_API_TOKEN        Authentication token with Mailgun");
+    eprintln!("  EMAIL_ENCRYPTION_KEY     Key used to decrypt encrypted emails in the team repo");
+    eprintln!("  ZULIP_USERNAME           Username of the Zulip bot");
+    eprintln!("  ZULIP_API_TOKEN          Autnentication token of the Zulip bot");
+    eprintln!("  GITHUB_STEP_SUMMARY      File the plan is written to with --github-step-summary");
+    eprintln!("  GITHUB_USERNAME_CACHE    Optional file caching GitHub usernames across runs");
+    eprintln!("  GITHUB_ADD_DELAY_MS      Optional delay after adding each GitHub team member");
+    eprintln!("  GITHUB_TOKENS            Optional comma-separated tokens used over GITHUB_TOKEN");
+    eprintln!("  GITHUB_EMAIL_DOMAINS     Optional email domains checked for GitHub org members");
+    eprintln!("  GITHUB_MAX_RETRIES       Optional retries of failed GitHub reads (3)");
+    eprintln!("  GITHUB_CONCURRENCY       Optional number of GitHub repos diffed at once (4)");
+    eprintln!("  GITHUB_APPLY_CONCURRENCY Optional number of GitHub teams/repos applied at once");
+    eprintln!("  GITHUB_LEAD_ORGS         Optional orgs (or !org/team) where leads maintain teams");
+    eprintln!("  GITHUB_DENY_LIST         Optional file of GitHub users to remove from the orgs");
+    eprintln!("  ZULIP_BATCH_SIZE         Optional max user ids per Zulip group update (500)");
+    eprintln!("  SYNC_FREEZE              Optional UTC start..end windows not applying changes");
+    eprintln!("  TEAM_DATA_SOURCE_REF     Optional commit of the team repo recorded in the logs");
 }
 
 fn app() -> anyhow::Result<()> {
+    let registry = service::registry();
+
     let mut dry_run = true;
-    let mut next_team_repo = false;
+    let mut dry_run_services = Vec::new();
     let mut only_print_plan = false;
+    let mut group_by_org = false;
+    let mut json_plan = false;
+    let mut fail_on_unmanaged = false;
+    let mut step_summary = false;
+    let mut report_per_user = false;
+    let mut remove_unmanaged_repo_grants = false;
+    let mut hold_out_of_band_members = false;
+    let mut check_health_files = false;
+    let mut check_collaborator_grants = false;
+    let mut check_idp_groups = false;
+    let mut max_removal_percent = None;
+    let mut confirmed_removals = Vec::new();
+    let mut interactive = false;
+    let mut audit_log = None;
+    let mut transaction_log = None;
+    let mut replay = None;
+    let mut explain_api = false;
+    let mut ignore_freeze = false;
     let mut team_repo = None;
+    let mut plan_output = None;
+    let mut report_html = None;
+    let mut apply_plan = None;
     let mut services = Vec::new();
-    for arg in std::env::args().skip(1) {
-        if next_team_repo {
-            team_repo = Some(arg);
-            next_team_repo = false;
-            continue;
-        }
+    let mut excluded_services = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
         match arg.as_str() {
             "--live" => dry_run = false,
-            "--team-repo" => next_team_repo = true,
+            "--dry-run" => {
+                let service = flag_value(&arg, args.next());
+                if !registry.iter().any(|s| s.name() == service) {
+                    eprintln!("unknown service for --dry-run: {service}");
+                    usage();
+                    std::process::exit(1);
+                }
+                dry_run_services.push(service.clone());
+                services.push(service);
+            }
+            "--exclude" => {
+                let service = flag_value(&arg, args.next());
+                if !registry.iter().any(|s| s.name() == service) {
+                    eprintln!("unknown service for --exclude: {service}");
+                    usage();
+                    std::process::exit(1);
+                }
+                excluded_services.push(service);
+            }
+            "--team-repo" => team_repo = Some(flag_value(&arg, args.next())),
+            "--plan-output" => plan_output = Some(flag_value(&arg, args.next())),
+            "--report-html" => report_html = Some(flag_value(&arg, args.next())),
+            "--apply-plan" => apply_plan = Some(flag_value(&arg, args.next())),
+            "--group-by" => match flag_value(&arg, args.next()).as_str() {
+                "service" => group_by_org = false,
+                "org" => group_by_org = true,
+                other => {
+                    eprintln!("unknown group for --group-by: {other}");
+                    usage();
+                    std::process::exit(1);
+                }
+            },
+            "--plan-format" => match flag_value(&arg, args.next()).as_str() {
+                "text" => json_plan = false,
+                "json" => json_plan = true,
+                other => {
+                    eprintln!("unknown format for --plan-format: {other}");
+                    usage();
+                    std::process::exit(1);
+                }
+            },
             "--help" => {
                 usage();
                 return Ok(());
             }
             "--only-print-plan" => only_print_plan = true,
-            service if AVAILABLE_SERVICES.contains(&service) => services.push(service.to_string()),
+            "--fail-on-unmanaged" => fail_on_unmanaged = true,
+            "--github-step-summary" => step_summary = true,
+            "--report-per-user" => report_per_user = true,
+            "--prune-repo-grants" => remove_unmanaged_repo_grants = true,
+            "--hold-out-of-band" => hold_out_of_band_members = true,
+            "--check-health-files" => check_health_files = true,
+            "--check-collaborators" => check_collaborator_grants = true,
+            "--idp-groups" => check_idp_groups = true,
+            "--interactive" => interactive = true,
+            "--audit-log" => audit_log = Some(flag_value(&arg, args.next()).into()),
+            "--replay-log" => transaction_log = Some(flag_value(&arg, args.next()).into()),
+            "--replay" => replay = Some(flag_value(&arg, args.next())),
+            "--explain-api" => explain_api = true,
+            "--ignore-freeze" => ignore_freeze = true,
+            "--max-removals" => {
+                let value = flag_value(&arg, args.next());
+                match value.parse() {
+                    Ok(percent) => max_removal_percent = Some(percent),
+                    Err(_) => {
+                        eprintln!("invalid percentage for --max-removals: {value}");
+                        usage();
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--allow-removals" => confirmed_removals.push(flag_value(&arg, args.next())),
+            service if registry.iter().any(|s| s.name() == service) => {
+                services.push(service.to_string())
+            }
             _ => {
                 eprintln!("unknown argument: {arg}");
                 usage();
@@ -60,63 +186,235 @@ fn app() -> anyhow::Result<()> {
         }
     }
 
+    if let Some(path) = replay {
+        if dry_run {
+            warn!("sync-team is running in dry mode, no changes will be applied.");
+        }
+        return service::replay_github(path.as_ref(), dry_run, explain_api);
+    }
+
     let team_api = team_repo
         .map(|p| TeamApi::Local(p.into()))
         .unwrap_or(TeamApi::Production);
 
+    if let Some(service) = services.iter().find(|s| excluded_services.contains(s)) {
+        anyhow::bail!("{service} is both selected and excluded with --exclude");
+    }
     if services.is_empty() {
         info!("no service to synchronize specified, defaulting to all services");
-        services = AVAILABLE_SERVICES
+        services = registry
             .iter()
-            .map(|s| (*s).to_string())
+            .map(|s| s.name().to_string())
+            .filter(|s| !excluded_services.contains(s))
             .collect();
     }
 
+    if !dry_run && !only_print_plan {
+        if let Ok(schedule) = std::env::var("SYNC_FREEZE") {
+            let windows = freeze::parse_schedule(&schedule)?;
+            if let Some(window) = freeze::active_window(&windows)? {
+                if ignore_freeze {
+                    warn!(
+                        "applying the changes despite the freeze until {}",
+                        window.until
+                    );
+                } else {
+                    warn!(
+                        "a freeze is active until {}, only printing the plan.",
+                        window.until
+                    );
+                    warn!("run the binary with --ignore-freeze to apply the changes anyway.");
+                    only_print_plan = true;
+                }
+            }
+        }
+    }
+
     if dry_run {
         warn!("sync-team is running in dry mode, no changes will be applied.");
         warn!("run the binary with the --live flag to apply the changes.");
+    } else {
+        for service in &dry_run_services {
+            warn!("{service} is running in dry mode, no changes will be applied to it.");
+        }
     }
 
-    for service in services {
-        info!("synchronizing {}", service);
-        match service.as_str() {
-            "github" => {
-                let token = get_env("GITHUB_TOKEN")?;
-                let client =
-                    HttpClient::from_url_and_token("https://api.github.com/".to_string(), token)?;
-                let gh_read = Box::new(GitHubApiRead::from_client(client.clone())?);
-                let teams = team_api.get_teams()?;
-                let repos = team_api.get_repos()?;
-                let diff = create_diff(gh_read, teams, repos)?;
-                info!("{}", diff);
-                if !only_print_plan {
-                    let gh_write = GitHubWrite::new(client, dry_run)?;
-                    diff.apply(&gh_write)?;
-                }
-            }
-            "mailgun" => {
-                let token = get_env("MAILGUN_API_TOKEN")?;
-                let encryption_key = get_env("EMAIL_ENCRYPTION_KEY")?;
-                mailgun::run(&token, &encryption_key, &team_api, dry_run)?;
-            }
-            "zulip" => {
-                let username = get_env("ZULIP_USERNAME")?;
-                let token = get_env("ZULIP_API_TOKEN")?;
-                let sync = SyncZulip::new(username, token, &team_api, dry_run)?;
-                let diff = sync.diff_all()?;
-                info!("{}", diff);
-                if !only_print_plan {
-                    diff.apply(&sync)?;
-                }
+    let options = Options {
+        team_api,
+        dry_run,
+        dry_run_services,
+        group_by_org,
+        fail_on_unmanaged,
+        report_per_user,
+        remove_unmanaged_repo_grants,
+        hold_out_of_band_members,
+        check_health_files,
+        check_collaborator_grants,
+        check_idp_groups,
+        max_removal_percent,
+        confirmed_removals,
+        interactive,
+        audit_log,
+        transaction_log,
+        explain_api,
+    };
+    // The plan saved by a previous run, that the current plan must match to be applied
+    let expected_plans = match &apply_plan {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read the plan at {path}"))?;
+            let plans: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse the plan at {path}"))?;
+            Some(plans)
+        }
+        None => None,
+    };
+    let mut plans = serde_json::Map::new();
+    let mut plan_texts = Vec::new();
+    // The checks failed by the plans, reported once every service was planned or applied
+    let mut failed_checks = Vec::new();
+    for service in registry
+        .iter()
+        .filter(|s| services.iter().any(|n| n == s.name()))
+    {
+        info!("synchronizing {}", service.name());
+        let plan = service.diff(&options)?;
+        let plan_text = plan.to_string();
+        if !json_plan {
+            info!("{plan_text}");
+        }
+        if step_summary {
+            write_step_summary(service.name(), &plan_text)?;
+        }
+        let json = plan.to_json()?;
+        if let Some(expected_plans) = &expected_plans {
+            let expected = expected_plans
+                .get(service.name())
+                .cloned()
+                .map(canonical_plan);
+            if expected != Some(canonical_plan(json.clone())) {
+                anyhow::bail!(
+                    "the plan of {} doesn't match the saved plan anymore, compute a new one",
+                    service.name()
+                );
             }
-            _ => panic!("unknown service: {service}"),
         }
+        plans.insert(service.name().to_string(), json);
+        plan_texts.push((service.name().to_string(), plan_text));
+        if let Err(err) = plan.check() {
+            failed_checks.push(err);
+        }
+        if !only_print_plan {
+            plan.apply()?;
+        }
+    }
+
+    if json_plan {
+        println!("{}", serde_json::to_string_pretty(&plans)?);
+    }
+
+    if let Some(path) = plan_output {
+        std::fs::write(&path, serde_json::to_string_pretty(&plans)?)
+            .with_context(|| format!("failed to write the plan to {path}"))?;
     }
 
+    if let Some(path) = report_html {
+        std::fs::write(&path, report::html_report(&plan_texts))
+            .with_context(|| format!("failed to write the HTML report to {path}"))?;
+    }
+
+    let mut failed_checks = failed_checks.into_iter();
+    if let Some(err) = failed_checks.next() {
+        for other in failed_checks {
+            error!("{other:#}");
+        }
+        return Err(err);
+    }
     Ok(())
 }
 
-fn get_env(key: &str) -> anyhow::Result<String> {
+/// The fields of the plans listing changes (or their subjects) in the order GitHub and the other
+/// services return data in. Other arrays, like the `(old, new)` pairs of updates, are ordered.
+const UNORDERED_PLAN_LISTS: &[&str] = &[
+    "account_migrations",
+    "team_diffs",
+    "repo_diffs",
+    "member_diffs",
+    "members",
+    "duplicate_definitions",
+    "idp_groups",
+    "child_teams",
+    "org_member_removals",
+    "permissions",
+    "permission_diffs",
+    "pending_invitations",
+    "branch_protections",
+    "branch_protection_diffs",
+    "app_installations",
+    "app_installation_diffs",
+    "list_diffs",
+    "user_group_diffs",
+    "member_ids",
+    "member_id_additions",
+    "member_id_deletions",
+    "held_member_id_deletions",
+];
+
+/// Normalizes a JSON plan so that it can be compared with a saved one, by sorting the
+/// [`UNORDERED_PLAN_LISTS`].
+fn canonical_plan(plan: serde_json::Value) -> serde_json::Value {
+    match plan {
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonical_plan).collect())
+        }
+        serde_json::Value::Object(fields) => serde_json::Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match canonical_plan(value) {
+                        serde_json::Value::Array(mut items)
+                            if UNORDERED_PLAN_LISTS.contains(&key.as_str()) =>
+                        {
+                            items.sort_by_cached_key(|item| item.to_string());
+                            serde_json::Value::Array(items)
+                        }
+                        value => value,
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Appends the plan of a service to the GitHub Actions job summary, as a collapsible section.
+fn write_step_summary(service: &str, plan: &str) -> anyhow::Result<()> {
+    let path = get_env("GITHUB_STEP_SUMMARY")?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open the job summary at {path}"))?;
+    writeln!(
+        file,
+        "<details><summary>{service}</summary>\n\n```\n{}\n```\n\n</details>\n",
+        plan.trim_end()
+    )
+    .with_context(|| format!("failed to write the job summary at {path}"))?;
+    Ok(())
+}
+
+/// Returns the value passed to a flag, exiting with the usage if it's missing.
+fn flag_value(flag: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| {
+        eprintln!("missing value for {flag}");
+        usage();
+        std::process::exit(1);
+    })
+}
+
+pub(crate) fn get_env(key: &str) -> anyhow::Result<String> {
     std::env::var(key).with_context(|| format!("failed to get the {key} environment variable"))
 }
 
@@ -140,3 +438,23 @@ fn init_log() {
     }
     env.init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical_plan() {
+        let plan = json!({"team_diffs": [{"name": "b"}, {"name": "a"}]});
+        assert_eq!(
+            canonical_plan(plan),
+            json!({"team_diffs": [{"name": "a"}, {"name": "b"}]})
+        );
+
+        // The old and new values of an update keep their order
+        let upgrade = json!({"permission_diffs": [{"diff": {"Update": ["triage", "write"]}}]});
+        let downgrade = json!({"permission_diffs": [{"diff": {"Update": ["write", "triage"]}}]});
+        assert_ne!(canonical_plan(upgrade), canonical_plan(downgrade));
+    }
+}
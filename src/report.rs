@@ -0,0 +1,158 @@
+use std::fmt::Write;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #24292f; }
+summary { cursor: pointer; padding: 0.3em 0; }
+details.service > summary { font-size: 1.4em; font-weight: bold; }
+details.entry { margin-left: 1em; border-left: 4px solid #d0d7de; padding-left: 0.5em; }
+details.entry.add { border-color: #2da44e; }
+details.entry.remove { border-color: #cf222e; }
+h2 { font-size: 1.2em; margin: 1em 0 0.3em; }
+h3 { font-size: 1em; color: #57606a; margin: 0.8em 0 0.3em; }
+pre { margin: 0 0 0.5em; white-space: pre-wrap; }
+.add { color: #116329; }
+.remove { color: #a40e26; }
+.warn { color: #9a6700; font-weight: bold; }
+";
+
+/// Renders the printed plans of the services, in order, as a self-contained HTML page for people
+/// reviewing the changes without reading the logs. Each service and each change is a collapsible
+/// section, and the lines adding, removing or holding something are colored.
+pub(crate) fn html_report(plans: &[(String, String)]) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>sync-team plan</title>\n");
+    let _ = writeln!(html, "<style>{STYLE}</style>");
+    html.push_str("</head>\n<body>\n<h1>sync-team plan</h1>\n");
+    for (service, plan) in plans {
+        let body = render_plan(plan);
+        let _ = writeln!(
+            html,
+            "<details class=\"service\" open>\n<summary>{} ({} changes)</summary>\n{}</details>",
+            escape(service),
+            body.changes,
+            body.html
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+struct RenderedPlan {
+    html: String,
+    changes: usize,
+}
+
+/// Turns the headings of a plan (`🏢 Org`, `💻 Team Diffs`, ...) into titles, and each change
+/// (a line without indentation, followed by its indented details) into a collapsible section.
+fn render_plan(plan: &str) -> RenderedPlan {
+    let mut html = String::new();
+    let mut changes = 0;
+    let mut in_change = false;
+    for line in plan.lines().filter(|line| !line.trim().is_empty()) {
+        let indented = line.starts_with(' ');
+        if in_change && !indented {
+            html.push_str("</pre></details>\n");
+            in_change = false;
+        }
+        if indented {
+            if !in_change {
+                html.push_str("<pre>");
+                in_change = true;
+            }
+            match line_class(line) {
+                Some(class) => {
+                    let _ = writeln!(html, "<span class=\"{class}\">{}</span>", escape(line));
+                }
+                None => {
+                    let _ = writeln!(html, "{}", escape(line));
+                }
+            }
+        } else if line.starts_with('🏢') {
+            let _ = writeln!(html, "<h2>{}</h2>", escape(line));
+        } else if line.starts_with('💻') {
+            let _ = writeln!(html, "<h3>{}</h3>", escape(line));
+        } else {
+            changes += 1;
+            let class = line_class(line)
+                .map(|c| format!(" {c}"))
+                .unwrap_or_default();
+            let _ = write!(
+                html,
+                "<details class=\"entry{class}\">\n<summary>{}</summary><pre>",
+                escape(line)
+            );
+            in_change = true;
+        }
+    }
+    if in_change {
+        html.push_str("</pre></details>\n");
+    }
+    RenderedPlan { html, changes }
+}
+
+/// Whether a line of a plan adds, removes or holds something, by the way the plans word these
+fn line_class(line: &str) -> Option<&'static str> {
+    let line = line.trim_start();
+    if ["⚠️", "⏸", "Holding", "Keeping"]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+        || line.contains("(weakening)")
+    {
+        Some("warn")
+    } else if ["➕", "Adding", "Creating", "Add "]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    {
+        Some("add")
+    } else if ["❌", "−", "Deleting", "Removing", "Remove "]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    {
+        Some("remove")
+    } else {
+        None
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_plan() {
+        let plan = "💻 Team Diffs:\n\
+                    📝 Editing team 'rust-lang/admins':\n  Adding member 'jan' with member role\n  \
+                    Deleting member 'mark'\n\
+                    ❌ Deleting team 'rust-lang/<old>'\n\
+                    💻 Repo Diffs:\n";
+        let rendered = render_plan(plan);
+        assert_eq!(rendered.changes, 2);
+        assert_eq!(
+            rendered.html,
+            "<h3>💻 Team Diffs:</h3>\n\
+             <details class=\"entry\">\n<summary>📝 Editing team &#39;rust-lang/admins&#39;:</summary>\
+             <pre><span class=\"add\">  Adding member &#39;jan&#39; with member role</span>\n\
+             <span class=\"remove\">  Deleting member &#39;mark&#39;</span>\n\
+             </pre></details>\n\
+             <details class=\"entry remove\">\n\
+             <summary>❌ Deleting team &#39;rust-lang/&lt;old&gt;&#39;</summary><pre></pre></details>\n\
+             <h3>💻 Repo Diffs:</h3>\n"
+        );
+    }
+}
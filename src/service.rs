@@ -0,0 +1,422 @@
+use crate::get_env;
+use crate::github::{
+    create_diff, read_transactions, replay, AuditLog, DiffOptions, GitHubApiRead, GitHubWrite,
+    GithubRead, HttpClient, LeadMaintainers, TransactionLog,
+};
+use crate::mailgun::SyncMailgun;
+use crate::team_api::TeamApi;
+use crate::zulip::SyncZulip;
+use anyhow::Context;
+use log::{error, warn};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Options shared by all the services, parsed from the command line.
+pub(crate) struct Options {
+    pub(crate) team_api: TeamApi,
+    pub(crate) dry_run: bool,
+    /// Services that run in dry mode even when `dry_run` is false
+    pub(crate) dry_run_services: Vec<String>,
+    pub(crate) group_by_org: bool,
+    pub(crate) fail_on_unmanaged: bool,
+    pub(crate) report_per_user: bool,
+    pub(crate) remove_unmanaged_repo_grants: bool,
+    pub(crate) hold_out_of_band_members: bool,
+    /// Report the repos missing community health files or a security policy
+    pub(crate) check_health_files: bool,
+    /// Report the direct collaborators of repos that already get access through the repo's teams
+    pub(crate) check_collaborator_grants: bool,
+    /// Leave alone the members of the GitHub teams synchronized from IdP groups
+    pub(crate) check_idp_groups: bool,
+    /// Hold removals that would remove more than this percentage of a team's or group's members,
+    /// along with reductions of the approvals required by branch protections
+    pub(crate) max_removal_percent: Option<usize>,
+    /// GitHub teams and repos (as `org/name`) and Zulip groups whose removals were acknowledged
+    pub(crate) confirmed_removals: Vec<String>,
+    /// Ask on the terminal before applying each change
+    pub(crate) interactive: bool,
+    /// File the applied membership changes are appended to
+    pub(crate) audit_log: Option<PathBuf>,
+    /// File the applied writes to GitHub teams are appended to, for replay
+    pub(crate) transaction_log: Option<PathBuf>,
+    /// Log each write to GitHub with the key fields of its response
+    pub(crate) explain_api: bool,
+}
+
+impl Options {
+    /// Whether the changes to the given service should only be planned
+    pub(crate) fn dry_run(&self, service: &str) -> bool {
+        self.dry_run || self.dry_run_services.iter().any(|s| s == service)
+    }
+}
+
+/// A service the contents of the team repo are synchronized to.
+///
+/// To add a new service, implement this trait and add it to [`registry`].
+pub(crate) trait Service {
+    /// The name used to select the service on the command line
+    fn name(&self) -> &'static str;
+
+    /// Compute the changes needed to bring the service in sync with the team repo
+    fn diff(&self, options: &Options) -> anyhow::Result<Box<dyn Plan>>;
+}
+
+/// The changes needed to bring a service in sync with the team repo.
+pub(crate) trait Plan: Display {
+    /// Fail if the plan contains changes that the options don't allow
+    fn check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The changes in a machine readable format, used to save the plan and check it later
+    fn to_json(&self) -> anyhow::Result<serde_json::Value>;
+
+    /// Apply the changes to the service
+    fn apply(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// All the services that can be synchronized, in the order they are synchronized in.
+pub(crate) fn registry() -> Vec<Box<dyn Service>> {
+    vec![Box::new(GitHub), Box::new(Mailgun), Box::new(Zulip)]
+}
+
+struct GitHub;
+
+impl Service for GitHub {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    fn diff(&self, options: &Options) -> anyhow::Result<Box<dyn Plan>> {
+        let client = github_client(options.explain_api)?;
+        let gh_read = Box::new(GitHubApiRead::from_client(client.clone())?);
+        let teams = options.team_api.get_teams()?;
+        let repos = options.team_api.get_repos()?;
+        let username_cache = std::env::var_os("GITHUB_USERNAME_CACHE").map(PathBuf::from);
+        let membership_add_delay = match std::env::var("GITHUB_ADD_DELAY_MS") {
+            Ok(ms) => {
+                Some(Duration::from_millis(ms.parse().with_context(|| {
+                    format!("invalid GITHUB_ADD_DELAY_MS: {ms}")
+                })?))
+            }
+            Err(_) => None,
+        };
+        // Reads are safe to run concurrently, so they are unless told otherwise. The documented
+        // GITHUB_CONCURRENCY can also be spelled GITHUB_READ_CONCURRENCY, like the apply one.
+        let read_concurrency = match ["GITHUB_CONCURRENCY", "GITHUB_READ_CONCURRENCY"]
+            .into_iter()
+            .find_map(|var| Some((var, std::env::var(var).ok()?)))
+        {
+            Some((var, n)) => n.parse().with_context(|| format!("invalid {var}: {n}"))?,
+            None => 4,
+        };
+        let apply_concurrency = match std::env::var("GITHUB_APPLY_CONCURRENCY") {
+            Ok(n) => n
+                .parse()
+                .with_context(|| format!("invalid GITHUB_APPLY_CONCURRENCY: {n}"))?,
+            Err(_) => 1,
+        };
+        let allowed_email_domains = std::env::var("GITHUB_EMAIL_DOMAINS")
+            .map(|domains| domains.split(',').map(|d| d.trim().to_string()).collect())
+            .unwrap_or_else(|_| Vec::new());
+        let lead_maintainers = std::env::var("GITHUB_LEAD_ORGS")
+            .map(|spec| LeadMaintainers::parse(&spec))
+            .unwrap_or_default();
+        let denied_users = match std::env::var("GITHUB_DENY_LIST") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read the deny list at {path}"))?
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|login| !login.is_empty())
+                .map(|login| login.to_string())
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+        let token_owners = gh_read.token_owners()?;
+        let mut diff = create_diff(
+            gh_read,
+            teams,
+            repos,
+            username_cache.as_deref(),
+            DiffOptions {
+                remove_unmanaged_repo_grants: options.remove_unmanaged_repo_grants,
+                allowed_email_domains,
+                check_health_files: options.check_health_files,
+                check_collaborator_grants: options.check_collaborator_grants,
+                check_idp_groups: options.check_idp_groups,
+                lead_maintainers,
+                denied_users,
+                read_concurrency,
+            },
+        )?;
+        if options.hold_out_of_band_members {
+            diff.hold_out_of_band_removals();
+        }
+        if let Some(max_percent) = options.max_removal_percent {
+            diff.hold_mass_removals(max_percent, &options.confirmed_removals);
+            diff.hold_review_count_reductions(&options.confirmed_removals);
+        }
+        diff.keep_token_owners(&token_owners);
+        // Looked up once, and only when one of the logs recording it is written
+        let source_ref = (options.audit_log.is_some() || options.transaction_log.is_some())
+            .then(|| options.team_api.source_ref())
+            .unwrap_or_default();
+        Ok(Box::new(GitHubPlan {
+            diff,
+            client,
+            dry_run: options.dry_run(self.name()),
+            group_by_org: options.group_by_org,
+            fail_on_unmanaged: options.fail_on_unmanaged,
+            report_per_user: options.report_per_user,
+            membership_add_delay,
+            apply_concurrency,
+            interactive: options.interactive,
+            audit_log: options
+                .audit_log
+                .clone()
+                .map(|path| AuditLog::new(path, source_ref.clone())),
+            transaction_log: options
+                .transaction_log
+                .clone()
+                .map(|path| TransactionLog::new(path, source_ref)),
+        }))
+    }
+}
+
+fn github_client(explain_api: bool) -> anyhow::Result<HttpClient> {
+    let tokens = match std::env::var("GITHUB_TOKENS") {
+        Ok(tokens) => tokens.split(',').map(|t| t.trim().to_string()).collect(),
+        Err(_) => vec![get_env("GITHUB_TOKEN")?],
+    };
+    let mut client =
+        HttpClient::from_url_and_tokens("https://api.github.com/".to_string(), tokens)?;
+    if let Ok(retries) = std::env::var("GITHUB_MAX_RETRIES") {
+        client = client.with_max_retries(
+            retries
+                .parse()
+                .with_context(|| format!("invalid GITHUB_MAX_RETRIES: {retries}"))?,
+        );
+    }
+    Ok(if explain_api {
+        client.with_explain()
+    } else {
+        client
+    })
+}
+
+/// Applies the writes to GitHub teams recorded in a transaction log again, in order
+pub(crate) fn replay_github(path: &Path, dry_run: bool, explain_api: bool) -> anyhow::Result<()> {
+    let transactions = read_transactions(path)?;
+    log::info!("replaying {} GitHub transactions", transactions.len());
+    replay(
+        transactions,
+        &GitHubWrite::new(github_client(explain_api)?, dry_run)?,
+    )
+}
+
+struct GitHubPlan {
+    diff: crate::github::Diff,
+    client: HttpClient,
+    dry_run: bool,
+    group_by_org: bool,
+    fail_on_unmanaged: bool,
+    report_per_user: bool,
+    membership_add_delay: Option<Duration>,
+    /// How many teams or repos are applied at the same time
+    apply_concurrency: usize,
+    interactive: bool,
+    audit_log: Option<AuditLog>,
+    transaction_log: Option<TransactionLog>,
+}
+
+impl Plan for GitHubPlan {
+    fn to_json(&self) -> anyhow::Result<serde_json::Value> {
+        let mut json = serde_json::to_value(&self.diff)?;
+        if let Some(fields) = json.as_object_mut() {
+            fields.insert(
+                "estimated_writes".to_string(),
+                serde_json::to_value(self.diff.estimated_writes())?,
+            );
+        }
+        Ok(json)
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.fail_on_unmanaged {
+            let unmanaged = self.diff.unmanaged_entities();
+            if !unmanaged.is_empty() {
+                for entity in &unmanaged {
+                    error!("unmanaged {entity}");
+                }
+                anyhow::bail!(
+                    "found {} GitHub entities not managed by the team repo",
+                    unmanaged.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(self: Box<Self>) -> anyhow::Result<()> {
+        let mut gh_write = GitHubWrite::new(self.client, self.dry_run)?;
+        if let Some(delay) = self.membership_add_delay {
+            gh_write = gh_write.with_membership_add_delay(delay);
+        }
+        if let Some(audit_log) = self.audit_log {
+            gh_write = gh_write.with_audit_log(audit_log);
+        }
+        if let Some(transaction_log) = self.transaction_log {
+            gh_write = gh_write.with_transaction_log(transaction_log);
+        }
+        let mut diff = self.diff;
+        let skipped = if self.interactive && !self.dry_run {
+            diff.confirm_each(confirm_on_terminal)
+        } else {
+            Vec::new()
+        };
+        let result = if self.report_per_user {
+            diff.apply_reporting_users(&gh_write)
+        } else if self.apply_concurrency > 1 {
+            diff.apply_concurrently(&gh_write, self.apply_concurrency)
+        } else {
+            diff.apply(&gh_write)
+        };
+        for change in &skipped {
+            warn!("skipped the change:\n{change}");
+        }
+        result
+    }
+}
+
+/// Asks the operator on the terminal whether to apply a change
+fn confirm_on_terminal(change: &str) -> bool {
+    print!("{change}Apply this change? [y/N] ");
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+impl Display for GitHubPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.group_by_org {
+            write!(f, "{}", self.diff.by_org())?;
+        } else {
+            write!(f, "{}", self.diff)?;
+        }
+        let writes = self.diff.estimated_writes();
+        if !writes.is_empty() {
+            let kinds = writes
+                .iter()
+                .map(|(kind, n)| format!("{kind}: {n}"))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "📊 Applying this plan would make ~{} writes ({})",
+                writes.values().sum::<usize>(),
+                kinds.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Mailgun;
+
+impl Service for Mailgun {
+    fn name(&self) -> &'static str {
+        "mailgun"
+    }
+
+    fn diff(&self, options: &Options) -> anyhow::Result<Box<dyn Plan>> {
+        let token = get_env("MAILGUN_API_TOKEN")?;
+        let encryption_key = get_env("EMAIL_ENCRYPTION_KEY")?;
+        let sync = SyncMailgun::new(
+            &token,
+            &encryption_key,
+            &options.team_api,
+            options.dry_run(self.name()),
+        )?;
+        let diff = sync.diff_all()?;
+        Ok(Box::new(MailgunPlan { sync, diff }))
+    }
+}
+
+struct MailgunPlan {
+    sync: SyncMailgun,
+    diff: crate::mailgun::Diff,
+}
+
+impl Plan for MailgunPlan {
+    fn to_json(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::to_value(&self.diff)?)
+    }
+
+    fn apply(self: Box<Self>) -> anyhow::Result<()> {
+        self.diff.apply(&self.sync)
+    }
+}
+
+impl Display for MailgunPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.diff)
+    }
+}
+
+struct Zulip;
+
+impl Service for Zulip {
+    fn name(&self) -> &'static str {
+        "zulip"
+    }
+
+    fn diff(&self, options: &Options) -> anyhow::Result<Box<dyn Plan>> {
+        let username = get_env("ZULIP_USERNAME")?;
+        let token = get_env("ZULIP_API_TOKEN")?;
+        let member_batch_size = match std::env::var("ZULIP_BATCH_SIZE") {
+            Ok(size) => match size.parse() {
+                Ok(size) if size > 0 => Some(size),
+                _ => anyhow::bail!("invalid ZULIP_BATCH_SIZE: {size}"),
+            },
+            Err(_) => None,
+        };
+        let sync = SyncZulip::new(
+            username,
+            token,
+            &options.team_api,
+            options.dry_run(self.name()),
+            member_batch_size,
+        )?;
+        let mut diff = sync.diff_all()?;
+        if let Some(max_percent) = options.max_removal_percent {
+            diff.hold_mass_removals(max_percent, &options.confirmed_removals);
+        }
+        Ok(Box::new(ZulipPlan { sync, diff }))
+    }
+}
+
+struct ZulipPlan {
+    sync: SyncZulip,
+    diff: crate::zulip::Diff,
+}
+
+impl Plan for ZulipPlan {
+    fn to_json(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::to_value(&self.diff)?)
+    }
+
+    fn apply(self: Box<Self>) -> anyhow::Result<()> {
+        self.diff.apply(&self.sync)
+    }
+}
+
+impl Display for ZulipPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.diff)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::utils::ResponseExt;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use std::borrow::Cow;
 use std::path::PathBuf;
 use std::process::Command;
@@ -40,6 +40,28 @@ impl TeamApi {
         self.req::<rust_team_data::v1::ZulipGroups>("zulip-groups.json")
     }
 
+    /// The commit of the team repo the data comes from, or `unknown` if it can't be found. The
+    /// production Team API doesn't publish it, so CI passes it through `TEAM_DATA_SOURCE_REF`.
+    pub(crate) fn source_ref(&self) -> String {
+        let source_ref = match std::env::var("TEAM_DATA_SOURCE_REF") {
+            Ok(source_ref) => Some(source_ref),
+            Err(_) => match self {
+                TeamApi::Production => None,
+                TeamApi::Local(path) => Command::new("git")
+                    .args(["rev-parse", "HEAD"])
+                    .current_dir(path)
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            },
+        };
+        source_ref.unwrap_or_else(|| {
+            warn!("the commit of the team repo is unknown, set TEAM_DATA_SOURCE_REF to record it");
+            "unknown".to_string()
+        })
+    }
+
     fn req<T: serde::de::DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
         match self {
             TeamApi::Production => {
@@ -2,6 +2,7 @@ use anyhow::Context;
 use reqwest::blocking::Response;
 use serde::de::DeserializeOwned;
 use std::str::FromStr;
+use std::sync::Mutex;
 
 pub trait ResponseExt {
     fn custom_error_for_status(self) -> anyhow::Result<Response>;
@@ -39,3 +40,82 @@ impl ResponseExt for Response {
         })
     }
 }
+
+/// Calls `f` on each item, with up to `limit` calls running at the same time on separate threads.
+/// No new call is started once one of them failed, and the first error is returned.
+pub(crate) fn for_each_concurrently<T: Send>(
+    items: Vec<T>,
+    limit: usize,
+    f: impl Fn(T) -> anyhow::Result<()> + Sync,
+) -> anyhow::Result<()> {
+    let items = Mutex::new(items.into_iter());
+    let error = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..limit.max(1) {
+            scope.spawn(|| loop {
+                if error.lock().unwrap().is_some() {
+                    break;
+                }
+                let Some(item) = items.lock().unwrap().next() else {
+                    break;
+                };
+                if let Err(err) = f(item) {
+                    error.lock().unwrap().get_or_insert(err);
+                }
+            });
+        }
+    });
+    match error.into_inner().unwrap() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// The number of days between the Unix epoch and a date of the proleptic Gregorian calendar
+pub(crate) fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    // Years start in March, so that the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_for_each_concurrently() {
+        // Both items wait for each other, so this only finishes if they run at the same time
+        let barrier = Barrier::new(2);
+        let events = Mutex::new(Vec::new());
+        for_each_concurrently(vec!["a", "b"], 2, |item| {
+            events.lock().unwrap().push(format!("start {item}"));
+            barrier.wait();
+            events.lock().unwrap().push(format!("end {item}"));
+            Ok(())
+        })
+        .unwrap();
+        // Items depending on the previous ones are only started once those are done
+        for_each_concurrently(vec!["c"], 2, |item| {
+            events.lock().unwrap().push(format!("start {item}"));
+            Ok(())
+        })
+        .unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert!(events[..2].iter().all(|e| e.starts_with("start")));
+        assert!(events[2..4].iter().all(|e| e.starts_with("end")));
+        assert_eq!(events[4], "start c");
+
+        let result = for_each_concurrently(vec![1, 2, 3], 1, |item| {
+            anyhow::ensure!(item != 2, "failed on {item}");
+            Ok(())
+        });
+        assert_eq!(result.unwrap_err().to_string(), "failed on 2");
+    }
+}
@@ -1,10 +1,20 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use reqwest::blocking::Client;
 use serde::Deserialize;
 
 const ZULIP_BASE_URL: &str = "https://rust-lang.zulipchat.com/api/v1";
 
+/// Messages of bad requests that can succeed when retried, e.g. because a just-created account
+/// isn't visible to the user groups API yet
+const TRANSIENT_BAD_REQUESTS: &[&str] = &["Invalid user ID"];
+/// How many times a transient bad request is retried before giving up
+const TRANSIENT_RETRIES: u32 = 3;
+const TRANSIENT_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How many user ids a user group membership update sends at most by default
+const DEFAULT_MEMBER_BATCH_SIZE: usize = 500;
+
 /// Access to the Zulip API
 #[derive(Clone)]
 pub(crate) struct ZulipApi {
@@ -12,6 +22,7 @@ pub(crate) struct ZulipApi {
     username: String,
     token: String,
     dry_run: bool,
+    member_batch_size: usize,
 }
 
 impl ZulipApi {
@@ -22,9 +33,17 @@ impl ZulipApi {
             username,
             token,
             dry_run,
+            member_batch_size: DEFAULT_MEMBER_BATCH_SIZE,
         }
     }
 
+    /// Split user group membership updates into requests of at most `size` added and removed
+    /// user ids, so that large updates don't fail as one giant request.
+    pub(crate) fn with_member_batch_size(mut self, size: usize) -> Self {
+        self.member_batch_size = size;
+        self
+    }
+
     /// Creates a Zulip user group with the supplied name, description, and members
     ///
     /// This is a noop if the user group already exists.
@@ -121,26 +140,40 @@ impl ZulipApi {
             return Ok(());
         }
 
-        let add_ids = serialize_as_array(add_ids);
-        let remove_ids = serialize_as_array(remove_ids);
-        let mut form = HashMap::new();
-        form.insert("add", add_ids.as_str());
-        form.insert("delete", remove_ids.as_str());
-
         let path = format!("/user_groups/{user_group_id}/members");
-        let response = self.req(reqwest::Method::POST, &path, Some(form))?;
-
-        if response.status() == 400 {
-            log::warn!(
-                "failed to update group membership with a bad request: {}",
-                response
-                    .text()
-                    .unwrap_or_else(|_| String::from("<BODY NOT DECODABLE>"))
-            );
-            return Ok(());
-        }
+        let batches = member_batches(add_ids, remove_ids, self.member_batch_size);
+        for (i, (add_ids, remove_ids)) in batches.iter().enumerate() {
+            let add_ids = serialize_as_array(add_ids);
+            let remove_ids = serialize_as_array(remove_ids);
+            let mut form = HashMap::new();
+            form.insert("add", add_ids.as_str());
+            form.insert("delete", remove_ids.as_str());
+
+            let result =
+                retry_transient_bad_requests(TRANSIENT_RETRIES, TRANSIENT_RETRY_DELAY, || {
+                    let response = self.req(reqwest::Method::POST, &path, Some(form.clone()))?;
+                    if response.status() == 400 {
+                        return Ok(Err(response
+                            .text()
+                            .unwrap_or_else(|_| String::from("<BODY NOT DECODABLE>"))));
+                    }
+                    response.error_for_status()?;
+                    Ok(Ok(()))
+                })?;
 
-        response.error_for_status()?;
+            match result {
+                Ok(()) if batches.len() > 1 => log::info!(
+                    "updated batch {}/{} of user group {}",
+                    i + 1,
+                    batches.len(),
+                    user_group_id
+                ),
+                Ok(()) => {}
+                Err(body) => {
+                    log::warn!("failed to update group membership with a bad request: {body}")
+                }
+            }
+        }
         Ok(())
     }
 
@@ -163,6 +196,47 @@ impl ZulipApi {
     }
 }
 
+/// Sends a request with `send`, which returns the body of the response as an error if it was a
+/// bad request, retrying it while the bad request is transient.
+fn retry_transient_bad_requests(
+    retries: u32,
+    delay: Duration,
+    mut send: impl FnMut() -> anyhow::Result<Result<(), String>>,
+) -> anyhow::Result<Result<(), String>> {
+    let mut attempt = 0;
+    loop {
+        match send()? {
+            Err(body)
+                if attempt < retries && TRANSIENT_BAD_REQUESTS.iter().any(|m| body.contains(m)) =>
+            {
+                attempt += 1;
+                log::info!("retrying a transient bad request ({attempt}/{retries}): {body}");
+                std::thread::sleep(delay);
+            }
+            result => return Ok(result),
+        }
+    }
+}
+
+/// Splits a membership update into batches of at most `size` added and removed ids in total
+fn member_batches<'a>(
+    mut add_ids: &'a [u64],
+    mut remove_ids: &'a [u64],
+    size: usize,
+) -> Vec<(&'a [u64], &'a [u64])> {
+    let size = size.max(1);
+    let mut batches = Vec::new();
+    while !add_ids.is_empty() || !remove_ids.is_empty() {
+        let (batch_add, rest_add) = add_ids.split_at(add_ids.len().min(size));
+        let (batch_remove, rest_remove) =
+            remove_ids.split_at(remove_ids.len().min(size - batch_add.len()));
+        batches.push((batch_add, batch_remove));
+        add_ids = rest_add;
+        remove_ids = rest_remove;
+    }
+    batches
+}
+
 /// Serialize a slice of numbers as a JSON array
 fn serialize_as_array(items: &[u64]) -> String {
     let items = items
@@ -201,3 +275,44 @@ pub(crate) struct ZulipUserGroup {
     pub(crate) name: String,
     pub(crate) members: Vec<u64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_transient_bad_requests() {
+        let mut responses = vec![
+            Ok(()),
+            Err(r#"{"msg":"Invalid user ID: 42","result":"error"}"#.to_string()),
+        ];
+        let result =
+            retry_transient_bad_requests(3, Duration::ZERO, || Ok(responses.pop().unwrap()));
+        assert_eq!(Ok(()), result.unwrap());
+        assert!(responses.is_empty());
+
+        let mut sent = 0;
+        let result = retry_transient_bad_requests(3, Duration::ZERO, || {
+            sent += 1;
+            Ok(Err(
+                r#"{"msg":"Invalid user group","result":"error"}"#.to_string()
+            ))
+        });
+        assert!(result.unwrap().is_err());
+        assert_eq!(1, sent);
+    }
+
+    #[test]
+    fn test_member_batches() {
+        let add_ids = (0..1200).collect::<Vec<_>>();
+        let remove_ids = (2000..2100).collect::<Vec<_>>();
+        let batches = member_batches(&add_ids, &remove_ids, 500);
+        let sizes = batches
+            .iter()
+            .map(|(add, remove)| (add.len(), remove.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(500, 0), (500, 0), (200, 100)], sizes);
+
+        assert!(member_batches(&[], &[], 500).is_empty());
+    }
+}
@@ -1,10 +1,10 @@
 mod api;
 
 use crate::team_api::TeamApi;
-use api::{ZulipApi, ZulipUserGroup};
+use api::{ZulipApi, ZulipUser, ZulipUserGroup};
 use rust_team_data::v1::ZulipGroupMember;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub(crate) struct SyncZulip {
     zulip_controller: ZulipController,
@@ -17,8 +17,12 @@ impl SyncZulip {
         token: String,
         team_api: &TeamApi,
         dry_run: bool,
+        member_batch_size: Option<usize>,
     ) -> anyhow::Result<Self> {
-        let zulip_api = ZulipApi::new(username, token, dry_run);
+        let mut zulip_api = ZulipApi::new(username, token, dry_run);
+        if let Some(size) = member_batch_size {
+            zulip_api = zulip_api.with_member_batch_size(size);
+        }
         let user_group_definitions = get_user_group_definitions(team_api, &zulip_api)?;
         let zulip_controller = ZulipController::new(zulip_api)?;
         Ok(Self {
@@ -87,13 +91,16 @@ impl SyncZulip {
             Ok(Some(UserGroupDiff::Update(UpdateUserGroupDiff {
                 name: user_group_name.to_owned(),
                 user_group_id,
+                member_count: existing_members.len(),
                 member_id_additions: add_ids,
                 member_id_deletions: remove_ids,
+                held_member_id_deletions: Vec::new(),
             })))
         }
     }
 }
 
+#[derive(serde::Serialize)]
 pub(crate) struct Diff {
     user_group_diffs: Vec<UserGroupDiff>,
 }
@@ -105,6 +112,25 @@ impl Diff {
         }
         Ok(())
     }
+
+    /// Hold the member removals of the user groups that would lose more than `max_percent` of
+    /// their members, as that's more likely caused by a mistake in the data than intended. Groups
+    /// listed in `confirmed` are exempt.
+    pub(crate) fn hold_mass_removals(&mut self, max_percent: usize, confirmed: &[String]) {
+        for user_group_diff in &mut self.user_group_diffs {
+            if let UserGroupDiff::Update(u) = user_group_diff {
+                if confirmed.contains(&u.name) || u.removal_percent() <= max_percent {
+                    continue;
+                }
+                log::warn!(
+                    "user group {}: {}% of the members would be removed, held pending confirmation",
+                    u.name,
+                    u.removal_percent()
+                );
+                u.held_member_id_deletions = std::mem::take(&mut u.member_id_deletions);
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for Diff {
@@ -117,6 +143,7 @@ impl std::fmt::Display for Diff {
     }
 }
 
+#[derive(serde::Serialize)]
 enum UserGroupDiff {
     Create(CreateUserGroupDiff),
     Update(UpdateUserGroupDiff),
@@ -140,6 +167,7 @@ impl std::fmt::Display for UserGroupDiff {
     }
 }
 
+#[derive(serde::Serialize)]
 struct CreateUserGroupDiff {
     name: String,
     description: String,
@@ -166,14 +194,26 @@ impl std::fmt::Display for CreateUserGroupDiff {
     }
 }
 
+#[derive(serde::Serialize)]
 struct UpdateUserGroupDiff {
     name: String,
     user_group_id: u64,
+    /// How many members the group has on Zulip
+    #[serde(skip)]
+    member_count: usize,
     member_id_additions: Vec<u64>,
     member_id_deletions: Vec<u64>,
+    /// Removals held because they'd remove too many members of the group at once
+    held_member_id_deletions: Vec<u64>,
 }
 
 impl UpdateUserGroupDiff {
+    /// The percentage of the members of the group that would be removed, held or not
+    fn removal_percent(&self) -> usize {
+        let removed = self.member_id_deletions.len() + self.held_member_id_deletions.len();
+        (removed * 100).checked_div(self.member_count).unwrap_or(0)
+    }
+
     fn apply(&self, sync: &SyncZulip) -> Result<(), anyhow::Error> {
         sync.zulip_controller.zulip_api.update_user_group_members(
             self.user_group_id,
@@ -194,6 +234,16 @@ impl std::fmt::Display for UpdateUserGroupDiff {
         for member_id in &self.member_id_deletions {
             writeln!(f, "    − {member_id}")?;
         }
+        if !self.held_member_id_deletions.is_empty() {
+            writeln!(
+                f,
+                "  {}% of the members would be removed, held pending confirmation:",
+                self.removal_percent()
+            )?;
+        }
+        for member_id in &self.held_member_id_deletions {
+            writeln!(f, "    ⏸ {member_id}")?;
+        }
         Ok(())
     }
 }
@@ -203,36 +253,57 @@ fn get_user_group_definitions(
     team_api: &TeamApi,
     zulip_api: &ZulipApi,
 ) -> anyhow::Result<BTreeMap<String, Vec<u64>>> {
-    let email_map = zulip_api
-        .get_users()?
-        .into_iter()
-        .filter_map(|u| u.email.map(|e| (e, u.user_id)))
-        .collect::<BTreeMap<_, _>>();
+    let users = zulip_api.get_users()?;
     let user_group_definitions = team_api
         .get_zulip_groups()?
         .groups
         .into_iter()
         .map(|(name, group)| {
-            let members = &group.members;
-            let member_ids = members
-                .iter()
-                .filter_map(|member| match member {
-                    ZulipGroupMember::Email(e) => {
-                        let id = email_map.get(e);
-                        if id.is_none() {
-                            log::warn!("no Zulip id found for '{}'", e);
-                        }
-                        id.copied()
-                    }
-                    ZulipGroupMember::Id(id) => Some(*id),
-                })
-                .collect::<Vec<_>>();
+            let member_ids = resolve_members(&name, &group.members, &users);
             (name, member_ids)
         })
         .collect();
     Ok(user_group_definitions)
 }
 
+/// Resolves the members of a user group to the ids of existing Zulip users.
+///
+/// Members that can't be resolved are dropped with a warning, as Zulip rejects the whole request
+/// if any of the ids is invalid, and one stale entry shouldn't block the rest of the group.
+fn resolve_members(
+    user_group_name: &str,
+    members: &[ZulipGroupMember],
+    users: &[ZulipUser],
+) -> Vec<u64> {
+    let email_map = users
+        .iter()
+        .filter_map(|u| u.email.as_deref().map(|e| (e, u.user_id)))
+        .collect::<BTreeMap<_, _>>();
+    let ids = users.iter().map(|u| u.user_id).collect::<BTreeSet<_>>();
+    members
+        .iter()
+        .filter_map(|member| match member {
+            ZulipGroupMember::Email(e) => {
+                let id = email_map.get(e.as_str());
+                if id.is_none() {
+                    log::warn!("no Zulip id found for '{e}' in user group '{user_group_name}'");
+                }
+                id.copied()
+            }
+            ZulipGroupMember::Id(id) => {
+                if ids.contains(id) {
+                    Some(*id)
+                } else {
+                    log::warn!(
+                        "dropping unknown Zulip id {id} from user group '{user_group_name}'"
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
 /// Interacts with the Zulip API
 struct ZulipController {
     /// User group name to Zulip user group id
@@ -286,3 +357,76 @@ impl ZulipController {
             .map(|u| u.members.to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_members() {
+        let users = vec![
+            ZulipUser {
+                email: Some("foo@example.com".into()),
+                user_id: 1,
+            },
+            ZulipUser {
+                email: None,
+                user_id: 2,
+            },
+        ];
+        let members = vec![
+            ZulipGroupMember::Email("foo@example.com".into()),
+            ZulipGroupMember::Email("missing@example.com".into()),
+            ZulipGroupMember::Id(2),
+            ZulipGroupMember::Id(3),
+        ];
+
+        assert_eq!(vec![1, 2], resolve_members("group", &members, &users));
+    }
+
+    #[test]
+    fn test_hold_mass_removals() {
+        let update = |name: &str, member_id_deletions: Vec<u64>| {
+            UserGroupDiff::Update(UpdateUserGroupDiff {
+                name: name.to_string(),
+                user_group_id: 1,
+                member_count: 4,
+                member_id_additions: vec![5],
+                member_id_deletions,
+                held_member_id_deletions: Vec::new(),
+            })
+        };
+        let mut diff = Diff {
+            user_group_diffs: vec![
+                update("few", vec![1]),
+                update("many", vec![1, 2, 3]),
+                update("confirmed", vec![1, 2, 3]),
+            ],
+        };
+        diff.hold_mass_removals(50, &["confirmed".to_string()]);
+
+        insta::assert_snapshot!(diff.to_string(), @r#"
+        💻 User Group Diffs:
+        📝 Updating user group:
+          Name: few
+          Members:
+            ➕ 5
+            − 1
+        📝 Updating user group:
+          Name: many
+          Members:
+            ➕ 5
+          75% of the members would be removed, held pending confirmation:
+            ⏸ 1
+            ⏸ 2
+            ⏸ 3
+        📝 Updating user group:
+          Name: confirmed
+          Members:
+            ➕ 5
+            − 1
+            − 2
+            − 3
+        "#);
+    }
+}